@@ -0,0 +1,111 @@
+use std::f32::consts::PI;
+use std::io;
+
+use super::AudioSink;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BeeperSettings {
+    pub frequency: f32,
+    pub waveform: Waveform,
+    // 0.0 - 1.0
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for BeeperSettings {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+            muted: false,
+        }
+    }
+}
+
+// The CHIP-8 buzzer, a single tone that sounds while the sound timer is non-zero.
+// Samples are generated as the VM runs and sent to the sinks once a frame.
+pub struct Beeper {
+    pub settings: BeeperSettings,
+    sinks: Vec<Box<dyn AudioSink>>,
+    // position in the current wave period, 0.0 - 1.0
+    phase: f32,
+    // fraction of a sample left over from the last advance
+    sample_remainder: f64,
+    pending: Vec<f32>,
+}
+
+impl Beeper {
+    pub fn new(settings: BeeperSettings) -> Self {
+        Self {
+            settings,
+            sinks: Vec::new(),
+            phase: 0.0,
+            sample_remainder: 0.0,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.settings.muted = !self.settings.muted;
+    }
+
+    // The next sample_count samples, silence when not playing. The phase keeps
+    // running through silence so the tone doesn't click when it starts.
+    pub fn generate(&mut self, playing: bool, sample_count: usize) -> Vec<f32> {
+        let settings = self.settings;
+        let amplitude = if playing && !settings.muted {
+            settings.volume.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let phase_step = settings.frequency / SAMPLE_RATE as f32;
+
+        (0..sample_count)
+            .map(|_| {
+                let value = match settings.waveform {
+                    Waveform::Square if self.phase < 0.5 => 1.0,
+                    Waveform::Square => -1.0,
+                    Waveform::Sine => (self.phase * 2.0 * PI).sin(),
+                };
+                self.phase = (self.phase + phase_step).fract();
+                value * amplitude
+            })
+            .collect()
+    }
+
+    // Called for every timer tick with the time it covers, so the sound
+    // starts and stops on the exact tick the sound timer does
+    pub fn advance(&mut self, playing: bool, seconds: f64) {
+        let samples = seconds * SAMPLE_RATE as f64 + self.sample_remainder;
+        self.sample_remainder = samples.fract();
+        let samples = self.generate(playing, samples as usize);
+        self.pending.extend(samples);
+    }
+
+    // Sends everything generated since the last flush to the sinks
+    pub fn flush(&mut self) {
+        for sink in &mut self.sinks {
+            sink.write(&self.pending);
+        }
+        self.pending.clear();
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.flush();
+        self.sinks.iter_mut().try_for_each(|sink| sink.finish())
+    }
+}
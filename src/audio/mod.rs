@@ -0,0 +1,5 @@
+mod beeper;
+mod sink;
+
+pub use beeper::{Beeper, BeeperSettings, Waveform, SAMPLE_RATE};
+pub use sink::{AudioSink, NullSink, RodioSink, WavSink};
@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rodio::buffer::SamplesBuffer;
+use rodio::Sink;
+
+use super::SAMPLE_RATE;
+
+// More queued chunks than this means the game fell behind the audio device,
+// so new chunks are dropped instead of adding latency
+const MAX_QUEUED_CHUNKS: usize = 4;
+// a stalled frame (a window drag, a breakpoint) would queue up stale audio
+const MAX_CHUNK_SAMPLES: usize = SAMPLE_RATE as usize / 10;
+
+// Somewhere to send mono samples at SAMPLE_RATE
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]);
+
+    // Called once when the emulator exits
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Discards everything, for machines without an audio device and --no-sound
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn write(&mut self, _samples: &[f32]) {}
+}
+
+pub struct RodioSink {
+    sink: Sink,
+}
+
+impl RodioSink {
+    pub fn new() -> Option<Self> {
+        rodio::default_output_device().map(|device| Self {
+            sink: Sink::new(&device),
+        })
+    }
+}
+
+impl AudioSink for RodioSink {
+    fn write(&mut self, samples: &[f32]) {
+        if samples.is_empty() || self.sink.len() > MAX_QUEUED_CHUNKS {
+            return;
+        }
+        let recent = &samples[samples.len().saturating_sub(MAX_CHUNK_SAMPLES)..];
+        self.sink
+            .append(SamplesBuffer::new(1, SAMPLE_RATE, recent.to_vec()));
+    }
+}
+
+// Records every sample as 16-bit mono PCM for --audio-out
+pub struct WavSink {
+    path: String,
+    // taken when the file is finalized
+    writer: Option<WavWriter<BufWriter<File>>>,
+}
+
+impl WavSink {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let writer = WavWriter::create(path, spec)
+            .map_err(|error| format!("Error with creating '{}': {}", path, error))?;
+        Ok(Self {
+            path: path.to_string(),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write(&mut self, samples: &[f32]) {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            if let Err(error) = writer.write_sample(sample) {
+                println!("Error with writing '{}': {}", self.path, error);
+                self.writer = None;
+                return;
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(writer) => writer
+                .finalize()
+                .map_err(|error| io::Error::other(error.to_string())),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::instructions::InstructionSet;
+use crate::instructions::{decode_opcode, InstructionSet};
 use crate::write_to_memory::write_font_data_to_memory;
 use crate::write_to_memory::write_rom_data_to_memory;
 use ggez::event::KeyCode;
@@ -6,11 +6,46 @@ use rand::Rng;
 
 pub const MEMORY_SIZE: usize = 0x1000; // 4096
 pub const CHIP8_RESERVED_MEMORY_SIZE: usize = 0x200; // 512
+pub const STACK_SIZE: usize = 16;
+// instructions run and timer ticks per second
+pub const CYCLES_PER_SECOND: u32 = 400;
 
-pub const SCALE: usize = 16;
 pub const DISPLAY_SIZE: [usize; 2] = [64, 32];
 
-pub const DEBUG_EXTRA_DISPLAY_SIZE: [f32; 2] = [300.0, 300.0];
+pub fn fetch_opcode(memory: &[u8], pc: u16) -> Result<u16, ()> {
+    if pc as usize + 1 >= MEMORY_SIZE {
+        return Err(());
+    }
+
+    Ok((memory[pc as usize] as u16) << 8 | memory[pc as usize + 1] as u16)
+}
+
+// Whether execute_instruction skips the opcode, 0000 is blank memory rather
+// than an instruction
+pub fn is_unknown_opcode(opcode: u16) -> bool {
+    opcode != 0
+        && matches!(
+            decode_opcode(opcode),
+            InstructionSet::MachineLanguageSubroutine(_) | InstructionSet::None
+        )
+}
+
+// The DXYN that last toggled a pixel on the display
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PixelSource {
+    pub program_counter: u16,
+    pub i: u16,
+    pub cycle: u64,
+    // the draw set VF because it erased a pixel somewhere in the sprite
+    pub collision: bool,
+}
+
+// An opcode the VM has no implementation for, e.g. a 0NNN machine code call
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct UnknownOpcode {
+    pub address: u16,
+    pub opcode: u16,
+}
 
 pub struct Chip8VM {
     pub memory: [u8; MEMORY_SIZE],
@@ -20,11 +55,16 @@ pub struct Chip8VM {
     pub sound_timer: u8,
     pub program_counter: u16,
     pub stack_pointer: u8,
-    pub stack: [u16; 16],
+    pub stack: [u16; STACK_SIZE],
     pub pressed_key: Option<u8>,
     pub display_data: [bool; DISPLAY_SIZE[0] * DISPLAY_SIZE[1]],
     pub waiting_for_key_press: bool,
     pub key_index_store: u8,
+    pub cycle_count: u64,
+    // one entry per display pixel, only kept while the debugger asks for it
+    pub pixel_sources: Option<Vec<Option<PixelSource>>>,
+    // set by the last step if it skipped an unknown opcode
+    pub unknown_opcode: Option<UnknownOpcode>,
 }
 
 impl Chip8VM {
@@ -33,15 +73,18 @@ impl Chip8VM {
             waiting_for_key_press: false,
             key_index_store: 0x00,
             display_data: [false; DISPLAY_SIZE[0] * DISPLAY_SIZE[1]],
-            memory: [0 as u8; MEMORY_SIZE],
-            v: [0 as u8; 16],
+            memory: [0_u8; MEMORY_SIZE],
+            v: [0_u8; 16],
             i: 0,
             delay_timer: 0,
             sound_timer: 0,
             program_counter: 0x200,
             stack_pointer: 0,
-            stack: [0 as u16; 16],
+            stack: [0_u16; STACK_SIZE],
             pressed_key: None,
+            cycle_count: 0,
+            pixel_sources: None,
+            unknown_opcode: None,
         };
 
         write_font_data_to_memory(&mut vm.memory);
@@ -50,6 +93,41 @@ impl Chip8VM {
         vm
     }
 
+    pub fn step(&mut self) -> u16 {
+        let opcode = fetch_opcode(&self.memory, self.program_counter).unwrap();
+        self.unknown_opcode = None;
+        self.execute_instruction(decode_opcode(opcode), opcode);
+
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+
+        self.program_counter += 2;
+        self.cycle_count += 1;
+
+        opcode
+    }
+
+    pub fn record_pixel_sources(&mut self, enabled: bool) {
+        if !enabled {
+            self.pixel_sources = None;
+        } else if self.pixel_sources.is_none() {
+            self.pixel_sources = Some(vec![None; DISPLAY_SIZE[0] * DISPLAY_SIZE[1]]);
+        }
+    }
+
+    pub fn pixel_source(&self, x: usize, y: usize) -> Option<PixelSource> {
+        if x >= DISPLAY_SIZE[0] || y >= DISPLAY_SIZE[1] {
+            return None;
+        }
+        self.pixel_sources
+            .as_ref()
+            .and_then(|sources| sources[y * DISPLAY_SIZE[0] + x])
+    }
+
     pub fn handle_keyboard_input(&mut self, keycode: KeyCode) {
         self.pressed_key = match keycode {
             KeyCode::Key1 => Some(0x1),
@@ -78,6 +156,9 @@ impl Chip8VM {
                 for i in 0..self.display_data.len() {
                     self.display_data[i] = false;
                 }
+                if let Some(sources) = &mut self.pixel_sources {
+                    sources.iter_mut().for_each(|source| *source = None);
+                }
             }
             InstructionSet::ReturnFromSubroutine => {
                 self.stack_pointer -= 1;
@@ -186,6 +267,7 @@ impl Chip8VM {
             }
             InstructionSet::DrawSprite(x, y, sprite_data) => {
                 let height = sprite_data;
+                let mut toggled_pixels = Vec::new();
 
                 self.v[0xF] = 0;
                 for i in 0..height {
@@ -203,12 +285,28 @@ impl Chip8VM {
 
                         let index = (y_pos as usize * DISPLAY_SIZE[0]) + x_pos as usize;
                         let value = self.display_data[index] as u8 ^ ((row >> (7 - j)) % 2);
-                        if self.display_data[index] == true && value == 0 {
+                        if self.display_data[index] && value == 0 {
                             self.v[0xF] = 1;
                         }
+                        if self.display_data[index] != (value == 1) {
+                            toggled_pixels.push(index);
+                        }
                         self.display_data[index] = value == 1;
                     }
                 }
+
+                if let Some(sources) = &mut self.pixel_sources {
+                    let source = PixelSource {
+                        program_counter: self.program_counter,
+                        i: self.i,
+                        // numbered like the trace, which counts this instruction
+                        cycle: self.cycle_count + 1,
+                        collision: self.v[0xF] == 1,
+                    };
+                    for index in toggled_pixels {
+                        sources[index] = Some(source);
+                    }
+                }
             }
             InstructionSet::SkipFollowingIfKeyCorrespondingToVxIsNotPressed(index) => {
                 if let Some(code) = self.pressed_key {
@@ -234,9 +332,13 @@ impl Chip8VM {
             InstructionSet::JumpToAddressWithV0Offset(address) => {
                 self.program_counter = address + self.v[0x0] as u16 - 0x02;
             }
-            _ => {
-                if opcode != 0 {
-                    println!("[{:04X?}]: {}", opcode, instruction.to_string());
+            InstructionSet::MachineLanguageSubroutine(_) | InstructionSet::None => {
+                // the frontends report it, the VM can't know where to
+                if is_unknown_opcode(opcode) {
+                    self.unknown_opcode = Some(UnknownOpcode {
+                        address: self.program_counter,
+                        opcode,
+                    });
                 }
             }
         }
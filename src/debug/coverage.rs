@@ -0,0 +1,102 @@
+use std::fmt;
+use std::fs;
+
+use crate::chip8_vm::MEMORY_SIZE;
+
+use super::{MemoryAccess, Profiler};
+
+const DEFAULT_COVERAGE_PATH: &str = "coverage.txt";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Coverage {
+    Untouched,
+    Code,
+    // read by DXYN or FX65, or written by FX33 or FX55
+    Data,
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Coverage::Untouched => "untouched",
+            Coverage::Code => "code",
+            Coverage::Data => "data",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CoverageRegion {
+    pub start: u16,
+    pub end: u16,
+    pub coverage: Coverage,
+}
+
+// Executed bytes count as code even if the program also reads them
+pub fn coverage(profiler: &Profiler, address: u16) -> Coverage {
+    let address = address as usize;
+    if profiler.counts(MemoryAccess::Execute)[address] > 0 {
+        Coverage::Code
+    } else if profiler.counts(MemoryAccess::Read)[address] > 0
+        || profiler.counts(MemoryAccess::Write)[address] > 0
+    {
+        Coverage::Data
+    } else {
+        Coverage::Untouched
+    }
+}
+
+// The whole memory split into runs of bytes with the same coverage
+pub fn coverage_regions(profiler: &Profiler) -> Vec<CoverageRegion> {
+    let mut regions: Vec<CoverageRegion> = Vec::new();
+
+    for address in 0..MEMORY_SIZE as u16 {
+        let kind = coverage(profiler, address);
+        match regions.last_mut() {
+            Some(region) if region.coverage == kind => region.end = address,
+            _ => regions.push(CoverageRegion {
+                start: address,
+                end: address,
+                coverage: kind,
+            }),
+        }
+    }
+
+    regions
+}
+
+pub fn coverage_report(profiler: &Profiler) -> String {
+    let regions = coverage_regions(profiler);
+    let byte_count = |kind: Coverage| -> usize {
+        regions
+            .iter()
+            .filter(|region| region.coverage == kind)
+            .map(|region| (region.end - region.start) as usize + 1)
+            .sum()
+    };
+
+    let mut report = format!(
+        "# {} code bytes, {} data bytes, {} untouched bytes\n",
+        byte_count(Coverage::Code),
+        byte_count(Coverage::Data),
+        byte_count(Coverage::Untouched)
+    );
+    for region in regions {
+        report.push_str(&format!(
+            "{:03X?}-{:03X?} {}\n",
+            region.start, region.end, region.coverage
+        ));
+    }
+    report
+}
+
+// Writes the coverage to the --coverage file, or coverage.txt without one
+pub fn save_coverage(profiler: &Profiler) -> std::io::Result<String> {
+    let path = profiler
+        .coverage_path
+        .clone()
+        .unwrap_or_else(|| DEFAULT_COVERAGE_PATH.to_string());
+    fs::write(&path, coverage_report(profiler))?;
+    Ok(path)
+}
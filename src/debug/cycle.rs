@@ -0,0 +1,58 @@
+use crate::audio::Beeper;
+use crate::chip8_vm::{Chip8VM, CYCLES_PER_SECOND};
+use crate::display::Recorder;
+
+use super::{Debugger, RegisterSnapshot, SymbolTable};
+
+// One tick of the VM clock as every frontend runs it. A running VM either
+// takes the key it waits for or runs an instruction, which is handed to
+// record. Returns whether an instruction ran, or the error to stop with
+// when an unknown opcode is fatal.
+pub fn run_cycle<F>(
+    chip8_state: &mut Chip8VM,
+    debugger: &mut Debugger,
+    beeper: &mut Beeper,
+    recorder: &mut Recorder,
+    record: F,
+) -> Result<bool, String>
+where
+    F: FnOnce(u16, u16, &RegisterSnapshot, &Chip8VM, &SymbolTable),
+{
+    let running = debugger.is_running();
+    let mut stepped = false;
+
+    if running && chip8_state.waiting_for_key_press {
+        if let Some(code) = chip8_state.pressed_key {
+            chip8_state.v[chip8_state.key_index_store as usize] = code;
+            chip8_state.waiting_for_key_press = false;
+        }
+    } else if running {
+        let program_counter = chip8_state.program_counter;
+        let registers = RegisterSnapshot::new(chip8_state);
+        let opcode = chip8_state.step();
+
+        record(
+            program_counter,
+            opcode,
+            &registers,
+            chip8_state,
+            &debugger.symbols,
+        );
+        debugger.after_step(chip8_state);
+        stepped = true;
+    }
+
+    // a paused VM keeps its sound timer, but a tone that never stops helps nobody
+    beeper.advance(
+        running && chip8_state.sound_timer > 0,
+        1.0 / CYCLES_PER_SECOND as f64,
+    );
+    if running {
+        recorder.advance(&chip8_state.display_data);
+    }
+
+    match debugger.unknown_opcode_error(chip8_state) {
+        Some(error) => Err(error),
+        None => Ok(stepped),
+    }
+}
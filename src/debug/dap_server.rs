@@ -0,0 +1,552 @@
+// Debug Adapter Protocol server, see:
+// https://microsoft.github.io/debug-adapter-protocol/specification
+//
+// Editors connect over TCP (the "debugServer" option in VS Code). Launch
+// arguments: "program" (ROM path), "sourceMap" (see SourceMap) and "stopOnEntry".
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+
+use serde_json::{json, Value};
+
+use crate::chip8_vm::{fetch_opcode, Chip8VM, MEMORY_SIZE};
+use crate::instructions::decode_opcode;
+use crate::utils::{parse_address, read_file_as_bytes};
+
+use super::remote::{RemoteConnection, RemoteListener};
+use super::{call_stack, Debugger, SourceMap, V_REGISTER_NAMES};
+
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+const STACK_REFERENCE: u64 = 2;
+
+fn format_address(address: u16) -> String {
+    format!("0x{:03X?}", address)
+}
+
+pub fn encode_message(message: &Value) -> String {
+    let content = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+}
+
+// Returns the first complete message in the buffer and how many bytes it used
+pub fn decode_message(buffer: &[u8]) -> Option<(Value, usize)> {
+    const SEPARATOR: &[u8] = b"\r\n\r\n";
+
+    let header_end = buffer
+        .windows(SEPARATOR.len())
+        .position(|window| window == SEPARATOR)?;
+    let header = String::from_utf8_lossy(&buffer[..header_end]);
+    let content_length = header
+        .lines()
+        .filter_map(|line| line.strip_prefix("Content-Length:"))
+        .find_map(|length| length.trim().parse::<usize>().ok())?;
+
+    let content_start = header_end + SEPARATOR.len();
+    let content_end = content_start.checked_add(content_length)?;
+    let content = buffer.get(content_start..content_end)?;
+    let message = serde_json::from_slice(content).unwrap_or(Value::Null);
+    Some((message, content_end))
+}
+
+#[derive(Default)]
+pub struct DapSession {
+    sequence: u64,
+    source_map: SourceMap,
+    source_breakpoints: HashMap<String, Vec<u16>>,
+    instruction_breakpoints: Vec<u16>,
+    stop_on_entry: bool,
+    waiting_for_stop: bool,
+}
+
+impl DapSession {
+    fn next_sequence(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Value {
+        json!({
+            "seq": self.next_sequence(),
+            "type": "event",
+            "event": event,
+            "body": body,
+        })
+    }
+
+    fn response(&mut self, request: &Value, result: Result<Value, String>) -> Value {
+        let mut response = json!({
+            "seq": self.next_sequence(),
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        response
+    }
+
+    fn stopped_event(&mut self, reason: &str) -> Value {
+        self.event(
+            "stopped",
+            json!({
+                "reason": reason,
+                "threadId": THREAD_ID,
+                "allThreadsStopped": true,
+            }),
+        )
+    }
+
+    // Stop event for a finished continue or step, once the debugger pauses
+    pub fn poll_stop(&mut self, chip8_state: &Chip8VM, debugger: &Debugger) -> Option<Value> {
+        if !self.waiting_for_stop || debugger.is_running() {
+            return None;
+        }
+
+        self.waiting_for_stop = false;
+        let reason = if debugger.unknown_opcode_hit.is_some() {
+            "exception"
+        } else if debugger.breakpoints.contains(&chip8_state.program_counter) {
+            "breakpoint"
+        } else {
+            "step"
+        };
+        Some(self.stopped_event(reason))
+    }
+
+    pub fn handle_message(
+        &mut self,
+        request: &Value,
+        chip8_state: &mut Chip8VM,
+        debugger: &mut Debugger,
+    ) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or("");
+        let arguments = &request["arguments"];
+        let mut events = Vec::new();
+
+        let result = match command {
+            "initialize" => {
+                events.push(self.event("initialized", json!({})));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsSetVariable": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsWriteMemoryRequest": true,
+                    "supportsDisassembleRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsSteppingGranularity": false,
+                }))
+            }
+            "launch" => self.launch(arguments, chip8_state),
+            "attach" => {
+                self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                Ok(json!({}))
+            }
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    events.push(self.stopped_event("entry"));
+                } else {
+                    self.waiting_for_stop = true;
+                    debugger.resume();
+                }
+                Ok(json!({}))
+            }
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments, debugger)),
+            "setInstructionBreakpoints" => {
+                Ok(self.set_instruction_breakpoints(arguments, debugger))
+            }
+            "threads" => Ok(json!({
+                "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }],
+            })),
+            "stackTrace" => Ok(self.stack_trace(chip8_state)),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    {
+                        "name": "Registers",
+                        "variablesReference": REGISTERS_REFERENCE,
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Stack",
+                        "variablesReference": STACK_REFERENCE,
+                        "expensive": false,
+                    },
+                ],
+            })),
+            "variables" => Ok(json!({ "variables": variables(arguments, chip8_state) })),
+            "setVariable" => set_variable(arguments, chip8_state),
+            "continue" => {
+                self.waiting_for_stop = true;
+                debugger.resume();
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.waiting_for_stop = true;
+                match command {
+                    "next" => debugger.step_over(chip8_state),
+                    "stepIn" => debugger.step_into(),
+                    _ => debugger.step_out(chip8_state),
+                }
+                Ok(json!({}))
+            }
+            "pause" => {
+                debugger.pause();
+                self.waiting_for_stop = false;
+                events.push(self.stopped_event("pause"));
+                Ok(json!({}))
+            }
+            "readMemory" => read_memory(arguments, chip8_state),
+            "writeMemory" => write_memory(arguments, chip8_state),
+            "disassemble" => Ok(disassemble(arguments, chip8_state)),
+            "disconnect" => {
+                debugger.resume();
+                Ok(json!({}))
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        };
+
+        let mut messages = vec![self.response(request, result)];
+        messages.append(&mut events);
+        messages
+    }
+
+    fn launch(&mut self, arguments: &Value, chip8_state: &mut Chip8VM) -> Result<Value, String> {
+        if let Some(program) = arguments["program"].as_str() {
+            let mut launched = Chip8VM::new(&read_file_as_bytes(program)?);
+            // the window's pixel inspector keeps working on the new VM
+            launched.record_pixel_sources(chip8_state.pixel_sources.is_some());
+            *chip8_state = launched;
+        }
+        if let Some(path) = arguments["sourceMap"].as_str() {
+            let text = std::fs::read_to_string(path)
+                .map_err(|error| format!("Error with reading source map: {}", error))?;
+            self.source_map = SourceMap::parse(&text)?;
+        }
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value, debugger: &mut Debugger) -> Value {
+        let path = arguments["source"]["path"]
+            .as_str()
+            .unwrap_or("")
+            .to_string();
+
+        for address in self.source_breakpoints.remove(&path).unwrap_or_default() {
+            debugger.breakpoints.remove(&address);
+        }
+
+        let mut addresses = Vec::new();
+        let breakpoints: Vec<Value> = arguments["breakpoints"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+            .iter()
+            .map(|breakpoint| {
+                let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+                let line_addresses = self.source_map.addresses(&path, line);
+                addresses.extend_from_slice(&line_addresses);
+                json!({
+                    "verified": !line_addresses.is_empty(),
+                    "line": line,
+                    "instructionReference": line_addresses.first().map(|&address| format_address(address)),
+                })
+            })
+            .collect();
+
+        debugger.breakpoints.extend(addresses.iter());
+        self.source_breakpoints.insert(path, addresses);
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value, debugger: &mut Debugger) -> Value {
+        for address in self.instruction_breakpoints.drain(..) {
+            debugger.breakpoints.remove(&address);
+        }
+
+        let breakpoints: Vec<Value> = arguments["breakpoints"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+            .iter()
+            .map(|breakpoint| {
+                let reference = breakpoint["instructionReference"].as_str().unwrap_or("");
+                let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+                match parse_address(reference).map(|address| address as i64 + offset) {
+                    Ok(address) if address >= 0 && (address as usize) < MEMORY_SIZE => {
+                        self.instruction_breakpoints.push(address as u16);
+                        json!({ "verified": true, "instructionReference": format_address(address as u16) })
+                    }
+                    _ => json!({ "verified": false, "message": "Invalid address" }),
+                }
+            })
+            .collect();
+
+        debugger
+            .breakpoints
+            .extend(self.instruction_breakpoints.iter());
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn stack_frame(&self, id: usize, name: String, address: u16) -> Value {
+        let mut frame = json!({
+            "id": id,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format_address(address),
+        });
+        if let Some(location) = self.source_map.location(address) {
+            frame["source"] = json!({ "path": location.path });
+            frame["line"] = json!(location.line);
+        }
+        frame
+    }
+
+    fn stack_trace(&self, chip8_state: &Chip8VM) -> Value {
+        let frames = call_stack(chip8_state);
+        let subroutine_name = |level: usize| match frames.get(level) {
+            Some(frame) => format!("sub_{:03X?}", frame.entry_address),
+            None => "main".to_string(),
+        };
+
+        let mut stack_frames =
+            vec![self.stack_frame(0, subroutine_name(0), chip8_state.program_counter)];
+        for (level, frame) in frames.iter().enumerate() {
+            stack_frames.push(self.stack_frame(
+                level + 1,
+                subroutine_name(level + 1),
+                frame.call_address,
+            ));
+        }
+
+        json!({
+            "stackFrames": stack_frames,
+            "totalFrames": frames.len() + 1,
+        })
+    }
+}
+
+fn variable(name: &str, value: String, memory_reference: Option<u16>) -> Value {
+    let mut variable = json!({
+        "name": name,
+        "value": value,
+        "variablesReference": 0,
+    });
+    if let Some(address) = memory_reference {
+        variable["memoryReference"] = json!(format_address(address));
+    }
+    variable
+}
+
+fn variables(arguments: &Value, chip8_state: &Chip8VM) -> Vec<Value> {
+    match arguments["variablesReference"].as_u64() {
+        Some(REGISTERS_REFERENCE) => {
+            let mut variables: Vec<Value> = V_REGISTER_NAMES
+                .iter()
+                .zip(chip8_state.v.iter())
+                .map(|(name, value)| variable(name, format!("0x{:02X?}", value), None))
+                .collect();
+            variables.push(variable(
+                "I",
+                format_address(chip8_state.i),
+                Some(chip8_state.i),
+            ));
+            variables.push(variable(
+                "PC",
+                format_address(chip8_state.program_counter),
+                Some(chip8_state.program_counter),
+            ));
+            variables.push(variable(
+                "SP",
+                format!("0x{:02X?}", chip8_state.stack_pointer),
+                None,
+            ));
+            variables.push(variable(
+                "DT",
+                format!("0x{:02X?}", chip8_state.delay_timer),
+                None,
+            ));
+            variables.push(variable(
+                "ST",
+                format!("0x{:02X?}", chip8_state.sound_timer),
+                None,
+            ));
+            variables
+        }
+        Some(STACK_REFERENCE) => chip8_state.stack[..chip8_state.stack_pointer as usize]
+            .iter()
+            .enumerate()
+            .map(|(index, &address)| {
+                variable(
+                    &format!("[{}]", index),
+                    format_address(address),
+                    Some(address),
+                )
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn set_variable(arguments: &Value, chip8_state: &mut Chip8VM) -> Result<Value, String> {
+    let name = arguments["name"].as_str().unwrap_or("");
+    let value = parse_address(arguments["value"].as_str().unwrap_or(""))?;
+
+    // return addresses are named after their stack index, like in variables
+    if let Some(index) = name
+        .strip_prefix('[')
+        .and_then(|name| name.strip_suffix(']'))
+    {
+        match index.parse::<usize>() {
+            Ok(index)
+                if index < chip8_state.stack_pointer as usize
+                    && (value as usize) < MEMORY_SIZE - 1 =>
+            {
+                chip8_state.stack[index] = value
+            }
+            _ => return Err(format!("Can't set '{}'", name)),
+        }
+        return Ok(json!({ "value": arguments["value"] }));
+    }
+
+    match name {
+        "I" if (value as usize) < MEMORY_SIZE => chip8_state.i = value,
+        "PC" if (value as usize) < MEMORY_SIZE - 1 => chip8_state.program_counter = value,
+        "DT" => chip8_state.delay_timer = value as u8,
+        "ST" => chip8_state.sound_timer = value as u8,
+        _ => match V_REGISTER_NAMES
+            .iter()
+            .position(|&register| register == name)
+        {
+            Some(index) => chip8_state.v[index] = value as u8,
+            None => return Err(format!("Can't set '{}'", name)),
+        },
+    }
+
+    Ok(json!({ "value": arguments["value"] }))
+}
+
+// Resolves memoryReference + offset, clamped to the VM memory
+fn memory_range(arguments: &Value, count: usize) -> Result<(usize, usize), String> {
+    let address = (parse_address(arguments["memoryReference"].as_str().unwrap_or(""))? as i64)
+        .saturating_add(arguments["offset"].as_i64().unwrap_or(0));
+    if address < 0 || address as usize >= MEMORY_SIZE {
+        return Err("Address out of memory".to_string());
+    }
+
+    let start = address as usize;
+    Ok((start, start.saturating_add(count).min(MEMORY_SIZE)))
+}
+
+fn read_memory(arguments: &Value, chip8_state: &Chip8VM) -> Result<Value, String> {
+    let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+    let (start, end) = memory_range(arguments, count)?;
+
+    Ok(json!({
+        "address": format_address(start as u16),
+        "data": base64::encode(&chip8_state.memory[start..end]),
+        "unreadableBytes": count - (end - start),
+    }))
+}
+
+fn write_memory(arguments: &Value, chip8_state: &mut Chip8VM) -> Result<Value, String> {
+    let data = base64::decode(arguments["data"].as_str().unwrap_or(""))
+        .map_err(|_| "Invalid memory data".to_string())?;
+    let (start, end) = memory_range(arguments, data.len())?;
+
+    chip8_state.memory[start..end].copy_from_slice(&data[..end - start]);
+    Ok(json!({ "offset": 0, "bytesWritten": end - start }))
+}
+
+fn disassemble(arguments: &Value, chip8_state: &Chip8VM) -> Value {
+    // all of these come from the client, so nothing may overflow
+    let start = (parse_address(arguments["memoryReference"].as_str().unwrap_or("")).unwrap_or(0)
+        as i64)
+        .saturating_add(arguments["offset"].as_i64().unwrap_or(0))
+        .saturating_add(
+            arguments["instructionOffset"]
+                .as_i64()
+                .unwrap_or(0)
+                .saturating_mul(2),
+        );
+    // there aren't more instructions than fit in memory
+    let count = arguments["instructionCount"]
+        .as_i64()
+        .unwrap_or(0)
+        .clamp(0, (MEMORY_SIZE / 2) as i64);
+
+    let instructions: Vec<Value> = (0..count)
+        .map(|index| start.saturating_add(index * 2))
+        .map(|address| {
+            let opcode = if (0..MEMORY_SIZE as i64).contains(&address) {
+                fetch_opcode(&chip8_state.memory, address as u16).ok()
+            } else {
+                None
+            };
+            match opcode {
+                Some(opcode) => json!({
+                    "address": format_address(address as u16),
+                    "instructionBytes": format!("{:02X?} {:02X?}", opcode >> 8, opcode & 0xFF),
+                    "instruction": decode_opcode(opcode).mnemonic(),
+                }),
+                None => json!({
+                    "address": format!("0x{:03X?}", address.max(0)),
+                    "instruction": "??",
+                    "presentationHint": "invalid",
+                }),
+            }
+        })
+        .collect();
+
+    json!({ "instructions": instructions })
+}
+
+pub struct DapServer {
+    listener: RemoteListener<DapSession>,
+}
+
+impl DapServer {
+    pub fn bind(address: &str) -> io::Result<Self> {
+        Ok(Self {
+            listener: RemoteListener::bind("DAP", address)?,
+        })
+    }
+
+    pub fn local_address(&self) -> io::Result<String> {
+        self.listener.local_address()
+    }
+
+    pub fn poll(&mut self, chip8_state: &mut Chip8VM, debugger: &mut Debugger) {
+        self.listener.poll(debugger, |connection, debugger| {
+            serve(connection, chip8_state, debugger)
+        });
+    }
+}
+
+fn serve(
+    connection: &mut RemoteConnection<DapSession>,
+    chip8_state: &mut Chip8VM,
+    debugger: &mut Debugger,
+) -> io::Result<()> {
+    while let Some((request, size)) = decode_message(&connection.input) {
+        connection.input.drain(..size);
+        for message in connection
+            .state
+            .handle_message(&request, chip8_state, debugger)
+        {
+            connection.send(encode_message(&message).as_bytes())?;
+        }
+        if request["command"] == "disconnect" {
+            return Err(ErrorKind::ConnectionAborted.into());
+        }
+    }
+
+    if let Some(event) = connection.state.poll_stop(chip8_state, debugger) {
+        connection.send(encode_message(&event).as_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::chip8_vm::{fetch_opcode, is_unknown_opcode, Chip8VM, UnknownOpcode};
+use crate::instructions::{decode_opcode, InstructionSet};
+
+use super::SymbolTable;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RunMode {
+    Paused,
+    Running,
+    StepInto,
+    StepOver {
+        return_address: u16,
+        stack_pointer: u8,
+    },
+    StepOut {
+        stack_pointer: u8,
+    },
+    RunToAddress(u16),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CallFrame {
+    pub call_address: u16,
+    pub call_opcode: u16,
+    pub return_address: u16,
+    pub entry_address: u16,
+}
+
+// Frames of the subroutine call stack, innermost call first
+pub fn call_stack(chip8_state: &Chip8VM) -> Vec<CallFrame> {
+    chip8_state.stack[..chip8_state.stack_pointer as usize]
+        .iter()
+        .rev()
+        .map(|&call_address| {
+            let call_opcode = fetch_opcode(&chip8_state.memory, call_address).unwrap_or(0);
+            CallFrame {
+                call_address,
+                call_opcode,
+                return_address: call_address + 2,
+                entry_address: call_opcode & 0x0FFF,
+            }
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct WatchpointHit {
+    pub address: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+// What to do when the VM skips an opcode it doesn't implement
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum UnknownOpcodeAction {
+    // report it and carry on, like the VM always did
+    #[default]
+    Log,
+    Break,
+    // stop the emulator, so headless runs notice ROMs needing extensions
+    Fatal,
+}
+
+pub struct Debugger {
+    pub run_mode: RunMode,
+    pub breakpoints: BTreeSet<u16>,
+    // watched memory addresses and the value they had after the last step
+    pub watchpoints: BTreeMap<u16, u8>,
+    pub watchpoint_hit: Option<WatchpointHit>,
+    // the unknown opcode the debugger stopped in front of
+    pub unknown_opcode_hit: Option<UnknownOpcode>,
+    pub symbols: SymbolTable,
+    pub on_unknown_opcode: UnknownOpcodeAction,
+}
+
+impl Debugger {
+    pub fn new(start_paused: bool) -> Self {
+        Self {
+            run_mode: if start_paused {
+                RunMode::Paused
+            } else {
+                RunMode::Running
+            },
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            watchpoint_hit: None,
+            unknown_opcode_hit: None,
+            symbols: SymbolTable::default(),
+            on_unknown_opcode: UnknownOpcodeAction::default(),
+        }
+    }
+
+    pub fn toggle_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.remove(&address) {
+            self.breakpoints.insert(address);
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, address: u16, chip8_state: &Chip8VM) {
+        self.watchpoints
+            .insert(address, chip8_state.memory[address as usize]);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) -> bool {
+        self.watchpoints.remove(&address).is_some()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.run_mode != RunMode::Paused
+    }
+
+    pub fn resume(&mut self) {
+        self.run_mode = RunMode::Running;
+    }
+
+    pub fn pause(&mut self) {
+        self.run_mode = RunMode::Paused;
+    }
+
+    pub fn step_into(&mut self) {
+        self.run_mode = RunMode::StepInto;
+    }
+
+    pub fn step_over(&mut self, chip8_state: &Chip8VM) {
+        let opcode = fetch_opcode(&chip8_state.memory, chip8_state.program_counter);
+        self.run_mode = match opcode.map(decode_opcode) {
+            Ok(InstructionSet::ExecuteSubroutine(_)) => RunMode::StepOver {
+                return_address: chip8_state.program_counter + 2,
+                stack_pointer: chip8_state.stack_pointer,
+            },
+            _ => RunMode::StepInto,
+        };
+    }
+
+    pub fn step_out(&mut self, chip8_state: &Chip8VM) {
+        self.run_mode = match chip8_state.stack_pointer {
+            0 => RunMode::StepInto,
+            stack_pointer => RunMode::StepOut { stack_pointer },
+        };
+    }
+
+    pub fn run_to(&mut self, address: u16) {
+        self.run_mode = RunMode::RunToAddress(address);
+    }
+
+    // Called after every executed instruction to decide whether to stop
+    pub fn after_step(&mut self, chip8_state: &Chip8VM) {
+        let finished = match self.run_mode {
+            RunMode::Paused | RunMode::Running => false,
+            RunMode::StepInto => true,
+            RunMode::StepOver {
+                return_address,
+                stack_pointer,
+            } => {
+                chip8_state.stack_pointer == stack_pointer
+                    && chip8_state.program_counter == return_address
+            }
+            RunMode::StepOut { stack_pointer } => chip8_state.stack_pointer < stack_pointer,
+            RunMode::RunToAddress(address) => chip8_state.program_counter == address,
+        };
+
+        self.watchpoint_hit = None;
+        for (&address, value) in self.watchpoints.iter_mut() {
+            let new = chip8_state.memory[address as usize];
+            if *value != new && self.watchpoint_hit.is_none() {
+                self.watchpoint_hit = Some(WatchpointHit {
+                    address,
+                    old: *value,
+                    new,
+                });
+            }
+            *value = new;
+        }
+
+        // stops in front of the opcode like a breakpoint does, resuming skips it
+        let address = chip8_state.program_counter;
+        self.unknown_opcode_hit = match fetch_opcode(&chip8_state.memory, address) {
+            Ok(opcode)
+                if self.on_unknown_opcode == UnknownOpcodeAction::Break
+                    && is_unknown_opcode(opcode) =>
+            {
+                Some(UnknownOpcode { address, opcode })
+            }
+            _ => None,
+        };
+
+        if finished
+            || self.watchpoint_hit.is_some()
+            || self.unknown_opcode_hit.is_some()
+            || self.breakpoints.contains(&chip8_state.program_counter)
+        {
+            self.run_mode = RunMode::Paused;
+        }
+    }
+
+    // The unknown opcode the last step skipped, for the frontend to report
+    // when those are only logged
+    pub fn unknown_opcode_skipped(&self, chip8_state: &Chip8VM) -> Option<UnknownOpcode> {
+        chip8_state
+            .unknown_opcode
+            .filter(|_| self.on_unknown_opcode == UnknownOpcodeAction::Log)
+    }
+
+    // The error to stop the emulator with when unknown opcodes are fatal
+    pub fn unknown_opcode_error(&self, chip8_state: &Chip8VM) -> Option<String> {
+        match chip8_state.unknown_opcode {
+            Some(unknown) if self.on_unknown_opcode == UnknownOpcodeAction::Fatal => Some(format!(
+                "Unknown opcode {:04X?} at {:03X?}",
+                unknown.opcode, unknown.address
+            )),
+            _ => None,
+        }
+    }
+}
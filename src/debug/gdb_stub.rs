@@ -0,0 +1,389 @@
+// Minimal GDB remote serial protocol stub, see:
+// https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+//
+// Register numbers: 0x00-0x0F V0-VF, 0x10 I, 0x11 PC, 0x12 SP, 0x13 DT, 0x14 ST.
+// Multi-byte registers are sent big-endian like everything else on CHIP-8.
+// gdb has no CHIP-8 architecture, so the layout is served as a target
+// description (qXfer:features:read) instead.
+
+use std::io::{self, ErrorKind};
+
+use crate::chip8_vm::{Chip8VM, MEMORY_SIZE, STACK_SIZE};
+
+use super::remote::{RemoteConnection, RemoteListener};
+use super::Debugger;
+
+const REGISTER_COUNT: usize = 21;
+const REGISTER_NAMES: [&str; REGISTER_COUNT] = [
+    "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7", "v8", "v9", "va", "vb", "vc", "vd", "ve", "vf",
+    "i", "pc", "sp", "dt", "st",
+];
+const INTERRUPT: u8 = 0x03;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+pub fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+pub fn encode_packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data))
+}
+
+fn register_size(register: usize) -> usize {
+    match register {
+        0x10 | 0x11 => 2,
+        _ => 1,
+    }
+}
+
+fn target_description() -> String {
+    let registers: String = REGISTER_NAMES
+        .iter()
+        .enumerate()
+        .map(|(register, name)| {
+            let register_type = match register {
+                0x10 => "data_ptr",
+                0x11 => "code_ptr",
+                _ => "uint8",
+            };
+            format!(
+                "<reg name=\"{}\" bitsize=\"{}\" regnum=\"{}\" type=\"{}\"/>",
+                name,
+                register_size(register) * 8,
+                register,
+                register_type
+            )
+        })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8.core\">{}</feature></target>",
+        registers
+    )
+}
+
+// "annex:offset,length" of a qXfer:features:read packet, the reply is the
+// requested part prefixed with 'm', or 'l' when it's the last one
+fn read_features(arguments: &str) -> Option<String> {
+    let mut parts = arguments.splitn(2, ':');
+    if parts.next()? != "target.xml" {
+        return None;
+    }
+    let mut range = parts.next()?.splitn(2, ',');
+    let offset = parse_hex(range.next()?)?;
+    let length = parse_hex(range.next()?)?;
+
+    let description = target_description();
+    let start = offset.min(description.len());
+    let end = offset.saturating_add(length).min(description.len());
+    let prefix = if end == description.len() { 'l' } else { 'm' };
+    Some(format!("{}{}", prefix, &description[start..end]))
+}
+
+fn read_register(chip8_state: &Chip8VM, register: usize) -> Option<u16> {
+    match register {
+        0x00..=0x0F => Some(chip8_state.v[register] as u16),
+        0x10 => Some(chip8_state.i),
+        0x11 => Some(chip8_state.program_counter),
+        0x12 => Some(chip8_state.stack_pointer as u16),
+        0x13 => Some(chip8_state.delay_timer as u16),
+        0x14 => Some(chip8_state.sound_timer as u16),
+        _ => None,
+    }
+}
+
+fn write_register(chip8_state: &mut Chip8VM, register: usize, value: u16) {
+    match register {
+        0x00..=0x0F => chip8_state.v[register] = value as u8,
+        0x10 if (value as usize) < MEMORY_SIZE => chip8_state.i = value,
+        0x11 if (value as usize) < MEMORY_SIZE - 1 => chip8_state.program_counter = value,
+        0x12 if (value as usize) <= STACK_SIZE => chip8_state.stack_pointer = value as u8,
+        0x13 => chip8_state.delay_timer = value as u8,
+        0x14 => chip8_state.sound_timer = value as u8,
+        _ => (),
+    }
+}
+
+fn encode_register(chip8_state: &Chip8VM, register: usize) -> String {
+    let value = read_register(chip8_state, register).unwrap_or(0);
+    match register_size(register) {
+        2 => format!("{:04x}", value),
+        _ => format!("{:02x}", value),
+    }
+}
+
+fn decode_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 == 1 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// "addr,length" as used by the m, M, Z and z packets
+fn parse_address_and_length(text: &str) -> Option<(usize, usize)> {
+    let mut parts = text.splitn(2, ',');
+    let address = parse_hex(parts.next()?)?;
+    let length = parse_hex(parts.next()?)?;
+
+    // both come from the client, their sum can overflow
+    address
+        .checked_add(length)
+        .filter(|&end| end <= MEMORY_SIZE)
+        .map(|_| (address, length))
+}
+
+// "type,addr,kind" as used by the Z and z packets
+fn parse_breakpoint(text: &str) -> Option<u16> {
+    let mut parts = text.splitn(3, ',');
+    match parts.next()? {
+        // software and hardware breakpoints are the same thing for us
+        "0" | "1" => match parse_hex(parts.next()?)? {
+            address if address < MEMORY_SIZE => Some(address as u16),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub enum Reply {
+    Packet(String),
+    WaitForStop,
+    Disconnect,
+}
+
+// Handles the body of a single packet. Execution packets don't get an
+// immediate reply, the stop reply is sent once the debugger pauses again.
+pub fn handle_packet(packet: &str, chip8_state: &mut Chip8VM, debugger: &mut Debugger) -> Reply {
+    const OK: &str = "OK";
+    const ERROR: &str = "E01";
+
+    let command = match packet.chars().next() {
+        Some(command) => command,
+        None => return Reply::Packet(String::new()),
+    };
+    let arguments = &packet[command.len_utf8()..];
+
+    let reply = match command {
+        '?' => format!("S{:02x}", SIGTRAP),
+        'g' => (0..REGISTER_COUNT)
+            .map(|register| encode_register(chip8_state, register))
+            .collect(),
+        'G' => match decode_hex_bytes(arguments) {
+            // every register has to be there, a short packet would zero the rest
+            Some(bytes) if bytes.len() == (0..REGISTER_COUNT).map(register_size).sum::<usize>() => {
+                let mut bytes = bytes.into_iter();
+                for register in 0..REGISTER_COUNT {
+                    let value = (0..register_size(register))
+                        .filter_map(|_| bytes.next())
+                        .fold(0_u16, |value, byte| value << 8 | byte as u16);
+                    write_register(chip8_state, register, value);
+                }
+                OK.to_string()
+            }
+            _ => ERROR.to_string(),
+        },
+        'p' => match parse_hex(arguments) {
+            Some(register) if register < REGISTER_COUNT => encode_register(chip8_state, register),
+            _ => ERROR.to_string(),
+        },
+        'P' => {
+            let mut parts = arguments.splitn(2, '=');
+            let register = parts.next().and_then(parse_hex);
+            let value = parts.next().and_then(decode_hex_bytes);
+            match (register, value) {
+                (Some(register), Some(bytes)) if register < REGISTER_COUNT => {
+                    let value = bytes
+                        .iter()
+                        .fold(0_u16, |value, &byte| value << 8 | byte as u16);
+                    write_register(chip8_state, register, value);
+                    OK.to_string()
+                }
+                _ => ERROR.to_string(),
+            }
+        }
+        'm' => match parse_address_and_length(arguments) {
+            Some((address, length)) => chip8_state.memory[address..address + length]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            None => ERROR.to_string(),
+        },
+        'M' => {
+            let mut parts = arguments.splitn(2, ':');
+            let range = parts.next().and_then(parse_address_and_length);
+            let bytes = parts.next().and_then(decode_hex_bytes);
+            match (range, bytes) {
+                (Some((address, length)), Some(bytes)) if bytes.len() == length => {
+                    chip8_state.memory[address..address + length].copy_from_slice(&bytes);
+                    OK.to_string()
+                }
+                _ => ERROR.to_string(),
+            }
+        }
+        'Z' => match parse_breakpoint(arguments) {
+            Some(address) => {
+                debugger.breakpoints.insert(address);
+                OK.to_string()
+            }
+            None => String::new(),
+        },
+        'z' => match parse_breakpoint(arguments) {
+            Some(address) => {
+                debugger.breakpoints.remove(&address);
+                OK.to_string()
+            }
+            None => String::new(),
+        },
+        's' | 'c' => {
+            if let Some(address) = parse_hex(arguments) {
+                write_register(chip8_state, 0x11, address as u16);
+            }
+            if command == 's' {
+                debugger.step_into();
+            } else {
+                debugger.resume();
+            }
+            return Reply::WaitForStop;
+        }
+        'D' => {
+            debugger.resume();
+            OK.to_string()
+        }
+        'k' => return Reply::Disconnect,
+        'H' => OK.to_string(),
+        _ => match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=1000;qXfer:features:read+".to_string()
+            }
+            _ if packet.starts_with("qXfer:features:read:") => {
+                read_features(&packet["qXfer:features:read:".len()..])
+                    .unwrap_or_else(|| ERROR.to_string())
+            }
+            // an empty reply tells gdb the packet isn't supported
+            _ => String::new(),
+        },
+    };
+
+    Reply::Packet(reply)
+}
+
+enum Incoming {
+    Interrupt,
+    Packet(String),
+}
+
+#[derive(Default)]
+struct ClientState {
+    waiting_for_stop: bool,
+}
+
+type Connection = RemoteConnection<ClientState>;
+
+pub struct GdbStub {
+    listener: RemoteListener<ClientState>,
+}
+
+impl GdbStub {
+    pub fn bind(address: &str) -> io::Result<Self> {
+        Ok(Self {
+            listener: RemoteListener::bind("GDB", address)?,
+        })
+    }
+
+    pub fn local_address(&self) -> io::Result<String> {
+        self.listener.local_address()
+    }
+
+    pub fn poll(&mut self, chip8_state: &mut Chip8VM, debugger: &mut Debugger) {
+        self.listener.poll(debugger, |connection, debugger| {
+            serve(connection, chip8_state, debugger)
+        });
+    }
+}
+
+fn serve(
+    connection: &mut Connection,
+    chip8_state: &mut Chip8VM,
+    debugger: &mut Debugger,
+) -> io::Result<()> {
+    while let Some(incoming) = next_incoming(connection)? {
+        match incoming {
+            Incoming::Interrupt => {
+                debugger.pause();
+                connection.state.waiting_for_stop = false;
+                send(connection, &format!("S{:02x}", SIGINT))?;
+            }
+            Incoming::Packet(packet) => match handle_packet(&packet, chip8_state, debugger) {
+                Reply::Packet(reply) => send(connection, &reply)?,
+                Reply::WaitForStop => connection.state.waiting_for_stop = true,
+                Reply::Disconnect => return Err(ErrorKind::ConnectionAborted.into()),
+            },
+        }
+    }
+
+    if connection.state.waiting_for_stop && !debugger.is_running() {
+        connection.state.waiting_for_stop = false;
+        let signal = if debugger.unknown_opcode_hit.is_some() {
+            SIGILL
+        } else {
+            SIGTRAP
+        };
+        send(connection, &format!("S{:02x}", signal))?;
+    }
+    Ok(())
+}
+
+fn next_incoming(connection: &mut Connection) -> io::Result<Option<Incoming>> {
+    let input = &mut connection.input;
+    loop {
+        match input.first() {
+            None => return Ok(None),
+            Some(&INTERRUPT) => {
+                input.remove(0);
+                return Ok(Some(Incoming::Interrupt));
+            }
+            Some(b'$') => break,
+            // acks and anything between packets
+            Some(_) => {
+                input.remove(0);
+            }
+        }
+    }
+
+    let end = match input.iter().position(|&byte| byte == b'#') {
+        Some(end) if input.len() >= end + 3 => end,
+        _ => return Ok(None),
+    };
+
+    let raw: Vec<u8> = input.drain(..end + 3).collect();
+    let data = String::from_utf8_lossy(&raw[1..end]).to_string();
+    let expected = std::str::from_utf8(&raw[end + 1..])
+        .ok()
+        .and_then(|text| u8::from_str_radix(text, 16).ok());
+
+    if expected == Some(checksum(&data)) {
+        connection.send(b"+")?;
+        Ok(Some(Incoming::Packet(data)))
+    } else {
+        connection.send(b"-")?;
+        next_incoming(connection)
+    }
+}
+
+fn send(connection: &mut Connection, data: &str) -> io::Result<()> {
+    connection.send(encode_packet(data).as_bytes())
+}
@@ -2,67 +2,413 @@ use imgui::*;
 use imgui_gfx_renderer::*;
 
 use super::super::super::chip8_vm::*;
+use super::super::super::display::{Area, Layout};
+use super::super::super::instructions::decode_opcode;
+use super::super::{
+    call_stack, coverage, save_coverage, Coverage, Debugger, Instruments, MemoryAccess, Profiler,
+    SymbolTable, Tracer, UnknownOpcodeAction,
+};
+use super::keypad::{render_keypad_window, KeypadState};
+use super::labels::{render_labels_window, LabelsState};
+use super::pixel_inspector::{render_pixel_inspector_window, PixelInspectorState};
+use super::sprite_viewer::{render_sprite_viewer_window, SpriteViewerState};
 
-pub fn render_gui(ui: &Ui, chip8_state: &Chip8VM) {
+#[derive(Copy, Clone, PartialEq, Default)]
+enum MemoryView {
+    #[default]
+    Plain,
+    Heatmap(MemoryAccess),
+    Coverage,
+}
+
+#[derive(Default)]
+pub struct GuiState {
+    selected_memory_address: u16,
+    disassembly_address: Option<u16>,
+    trace_filter: ImString,
+    memory_view: MemoryView,
+    sprite_viewer: SpriteViewerState,
+    keypad: KeypadState,
+    labels: LabelsState,
+    pixel_inspector: PixelInspectorState,
+}
+
+pub fn render_gui(
+    ui: &Ui,
+    layout: &Layout,
+    chip8_state: &mut Chip8VM,
+    debugger: &mut Debugger,
+    instruments: &mut Instruments,
+    gui_state: &mut GuiState,
+) {
     let flags = imgui::WindowFlags::NO_RESIZE
         | imgui::WindowFlags::NO_MOVE
         | imgui::WindowFlags::NO_COLLAPSE;
 
-    render_register_info_window(ui, chip8_state, flags);
-    render_memory_table(ui, chip8_state, flags);
+    render_register_info_window(ui, layout.registers, chip8_state, gui_state, flags);
+    render_disassembly_window(
+        ui,
+        layout.disassembly,
+        chip8_state,
+        debugger,
+        &instruments.profiler,
+        gui_state,
+        flags,
+    );
+    render_memory_table(
+        ui,
+        layout.memory_table,
+        chip8_state,
+        &mut instruments.profiler,
+        gui_state,
+        flags,
+    );
+    render_trace_window(ui, layout.trace, &mut instruments.tracer, gui_state, flags);
+    render_call_stack_window(
+        ui,
+        layout.call_stack,
+        chip8_state,
+        &debugger.symbols,
+        gui_state,
+        flags,
+    );
+
+    render_sprite_viewer_window(ui, chip8_state, &mut gui_state.sprite_viewer);
+    render_keypad_window(ui, chip8_state, &mut gui_state.keypad);
+    if let Some(address) = render_labels_window(
+        ui,
+        chip8_state,
+        &mut debugger.symbols,
+        &mut gui_state.labels,
+    ) {
+        gui_state.disassembly_address = Some(address);
+    }
+    if let Some(address) = render_pixel_inspector_window(
+        ui,
+        layout,
+        chip8_state,
+        &debugger.symbols,
+        &mut gui_state.pixel_inspector,
+    ) {
+        gui_state.disassembly_address = Some(address);
+    }
 }
 
-fn render_register_info_window(ui: &Ui, chip8_state: &Chip8VM, flags: WindowFlags) {
+// Hex input field that returns the new value once the user confirms it with Enter
+pub(super) fn input_hex(ui: &Ui, label: &ImStr, value: u16, digits: usize) -> Option<u16> {
+    let mut buffer = ImString::with_capacity(digits);
+    buffer.push_str(&format!("{:0width$X?}", value, width = digits));
+
+    ui.set_next_item_width(8.0 * digits as f32 + 12.0);
+    let confirmed = ui
+        .input_text(label, &mut buffer)
+        .chars_hexadecimal(true)
+        .chars_uppercase(true)
+        .auto_select_all(true)
+        .enter_returns_true(true)
+        .build();
+
+    if confirmed {
+        u16::from_str_radix(buffer.to_str(), 16).ok()
+    } else {
+        None
+    }
+}
+
+fn render_register_info_window(
+    ui: &Ui,
+    area: Area,
+    chip8_state: &mut Chip8VM,
+    gui_state: &mut GuiState,
+    flags: WindowFlags,
+) {
     Window::new(im_str!("Registers"))
-        .size(
-            [
-                DEBUG_EXTRA_DISPLAY_SIZE[0],
-                (DISPLAY_SIZE[1] * SCALE) as f32,
-            ],
-            imgui::Condition::Always,
-        )
-        .position(
-            [(DISPLAY_SIZE[0] * SCALE) as f32, 0.0],
-            imgui::Condition::Always,
-        )
+        .size(area.size, imgui::Condition::Always)
+        .position(area.position, imgui::Condition::Always)
         .flags(flags)
-        .build(&ui, || {
+        .build(ui, || {
             ui.text(im_str!("Main Registers: "));
             ui.separator();
+            ui.columns(2, im_str!("main_registers"), false);
             for i in 0..chip8_state.v.len() {
-                ui.text(im_str!("{:02X?}: {:02X?}", i, chip8_state.v[i]));
+                if i == chip8_state.v.len() / 2 {
+                    ui.next_column();
+                }
+                ui.text(im_str!("{:02X?}:", i));
+                ui.same_line(0.0);
+                if let Some(value) = input_hex(ui, &im_str!("##v{}", i), chip8_state.v[i] as u16, 2)
+                {
+                    chip8_state.v[i] = value as u8;
+                }
             }
+            ui.columns(1, im_str!("main_registers"), false);
             ui.separator();
             ui.text(im_str!("Other Registers: "));
             ui.separator();
-            ui.text(im_str!("i: {:02X?}", chip8_state.i));
+            if let Some(value) = input_hex(ui, im_str!("i"), chip8_state.i, 4) {
+                if (value as usize) < MEMORY_SIZE {
+                    chip8_state.i = value;
+                }
+            }
             ui.text(im_str!("stack pointer: {:02X?}", chip8_state.stack_pointer));
-            ui.text(im_str!("delay timer: {:02X?}", chip8_state.delay_timer));
-            ui.text(im_str!("sound timer: {:02X?}", chip8_state.sound_timer));
-            ui.text(im_str!(
-                "program counter: {:03X?}",
-                chip8_state.program_counter
-            ));
+            if let Some(value) = input_hex(
+                ui,
+                im_str!("delay timer"),
+                chip8_state.delay_timer as u16,
+                2,
+            ) {
+                chip8_state.delay_timer = value as u8;
+            }
+            if let Some(value) = input_hex(
+                ui,
+                im_str!("sound timer"),
+                chip8_state.sound_timer as u16,
+                2,
+            ) {
+                chip8_state.sound_timer = value as u8;
+            }
+            if let Some(value) = input_hex(
+                ui,
+                im_str!("program counter"),
+                chip8_state.program_counter,
+                3,
+            ) {
+                if (value as usize) < MEMORY_SIZE - 1 {
+                    chip8_state.program_counter = value;
+                }
+            }
+            ui.separator();
+            ui.text(im_str!("Tools: "));
+            ui.separator();
+            ui.checkbox(im_str!("sprites"), &mut gui_state.sprite_viewer.opened);
+            ui.same_line(0.0);
+            ui.checkbox(im_str!("keypad"), &mut gui_state.keypad.opened);
+            ui.same_line(0.0);
+            ui.checkbox(im_str!("labels"), &mut gui_state.labels.opened);
         });
 }
 
-fn render_memory_table(ui: &Ui, chip8_state: &Chip8VM, flags: WindowFlags) {
-    let memory_table_window_size = [
-        DEBUG_EXTRA_DISPLAY_SIZE[0] + (DISPLAY_SIZE[0] * SCALE) as f32,
-        DEBUG_EXTRA_DISPLAY_SIZE[1],
-    ];
+fn render_disassembly_window(
+    ui: &Ui,
+    area: Area,
+    chip8_state: &Chip8VM,
+    debugger: &mut Debugger,
+    profiler: &Profiler,
+    gui_state: &mut GuiState,
+    flags: WindowFlags,
+) {
+    const LINES_BEFORE_PC: u16 = 8;
+    const LINE_COUNT: u16 = 24;
+    const LABEL_COLOR: [f32; 4] = [1.0, 0.85, 0.4, 1.0];
+
+    Window::new(im_str!("Disassembly"))
+        .size(area.size, imgui::Condition::Always)
+        .position(area.position, imgui::Condition::Always)
+        .flags(flags)
+        .build(ui, || {
+            if debugger.is_running() {
+                if ui.small_button(im_str!("Pause")) {
+                    debugger.pause();
+                }
+            } else if ui.small_button(im_str!("Continue")) {
+                debugger.resume();
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Step Into")) {
+                debugger.step_into();
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Step Over")) {
+                debugger.step_over(chip8_state);
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Step Out")) {
+                debugger.step_out(chip8_state);
+            }
+            ui.text(im_str!("state: {:?}", debugger.run_mode));
+            ui.same_line(0.0);
+            let mut break_on_unknown = debugger.on_unknown_opcode == UnknownOpcodeAction::Break;
+            if ui.checkbox(im_str!("break on unknown opcodes"), &mut break_on_unknown) {
+                debugger.on_unknown_opcode = if break_on_unknown {
+                    UnknownOpcodeAction::Break
+                } else {
+                    UnknownOpcodeAction::Log
+                };
+            }
+            if let Some(unknown) = debugger.unknown_opcode_hit {
+                ui.text_colored(
+                    [1.0, 0.0, 0.0, 1.0],
+                    im_str!(
+                        "unknown opcode {:04X?} at {:03X?}",
+                        unknown.opcode,
+                        unknown.address
+                    ),
+                );
+            }
+            if let Some(address) = gui_state.disassembly_address {
+                ui.text(im_str!("viewing: {:03X?}", address));
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Follow PC")) {
+                    gui_state.disassembly_address = None;
+                }
+            }
+            ui.separator();
+
+            let start = gui_state
+                .disassembly_address
+                .unwrap_or(chip8_state.program_counter)
+                .saturating_sub(LINES_BEFORE_PC * 2);
+            for line in 0..LINE_COUNT {
+                let address = start + line * 2;
+                let opcode = match fetch_opcode(&chip8_state.memory, address) {
+                    Ok(opcode) => opcode,
+                    Err(_) => break,
+                };
+                if let Some(symbol) = debugger.symbols.get(address) {
+                    ui.text_colored(LABEL_COLOR, im_str!("{}:", symbol.name));
+                    if let Some(comment) = &symbol.comment {
+                        ui.same_line(0.0);
+                        ui.text_disabled(im_str!("; {}", comment));
+                    }
+                }
+                // bytes the program only used as data aren't worth decoding
+                let is_data = coverage(profiler, address) == Coverage::Data;
+                let listing = if is_data {
+                    format!("DB {:02X?}, {:02X?}", opcode >> 8, opcode & 0xFF)
+                } else {
+                    debugger.symbols.mnemonic(&decode_opcode(opcode))
+                };
+                let text = im_str!(
+                    "{}{:03X?}: {:04X?}  {}",
+                    if debugger.breakpoints.contains(&address) {
+                        "*"
+                    } else {
+                        " "
+                    },
+                    address,
+                    opcode,
+                    listing
+                );
+
+                let color = if is_data {
+                    Some(ui.push_style_color(StyleColor::Text, coverage_color(Coverage::Data)))
+                } else {
+                    None
+                };
+                let clicked = Selectable::new(&text)
+                    .selected(address == chip8_state.program_counter)
+                    .build(ui);
+                if let Some(color) = color {
+                    color.pop(ui);
+                }
+                if clicked {
+                    debugger.run_to(address);
+                }
+                if ui.is_item_clicked(MouseButton::Right) {
+                    debugger.toggle_breakpoint(address);
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(
+                        "Click to run to this address, right click to toggle breakpoint",
+                    );
+                }
+            }
+        });
+}
+
+// Blue for rarely touched bytes up to red for the busiest ones, on a log scale
+fn heatmap_color(count: u64, max_count: u64) -> [f32; 4] {
+    if count == 0 {
+        return [0.4, 0.4, 0.4, 1.0];
+    }
+    let heat = ((count as f32).ln_1p() / (max_count as f32).ln_1p()).min(1.0);
+    [0.3 + 0.7 * heat, 0.3 * (1.0 - heat), 1.0 - heat, 1.0]
+}
+
+fn coverage_color(coverage: Coverage) -> [f32; 4] {
+    match coverage {
+        Coverage::Untouched => [0.4, 0.4, 0.4, 1.0],
+        Coverage::Code => [0.3, 0.9, 0.3, 1.0],
+        Coverage::Data => [1.0, 0.7, 0.2, 1.0],
+    }
+}
+
+fn render_memory_table(
+    ui: &Ui,
+    area: Area,
+    chip8_state: &mut Chip8VM,
+    profiler: &mut Profiler,
+    gui_state: &mut GuiState,
+    flags: WindowFlags,
+) {
     Window::new(im_str!("Memory Table"))
-        .size(
-            [memory_table_window_size[0], memory_table_window_size[1]],
-            imgui::Condition::Always,
-        )
-        .position(
-            [0.0, (DISPLAY_SIZE[1] * SCALE) as f32],
-            imgui::Condition::Always,
-        )
+        .size(area.size, imgui::Condition::Always)
+        .position(area.position, imgui::Condition::Always)
         .flags(flags)
-        .build(&ui, || {
-            let col_count = memory_table_window_size[0] as usize / 22;
+        .build(ui, || {
+            if let Some(address) =
+                input_hex(ui, im_str!("address"), gui_state.selected_memory_address, 3)
+            {
+                if (address as usize) < MEMORY_SIZE {
+                    gui_state.selected_memory_address = address;
+                }
+            }
+            ui.same_line(0.0);
+            let selected = gui_state.selected_memory_address as usize;
+            if let Some(value) =
+                input_hex(ui, im_str!("value"), chip8_state.memory[selected] as u16, 2)
+            {
+                chip8_state.memory[selected] = value as u8;
+            }
+
+            ui.text(im_str!("show:"));
+            for (label, view) in [
+                (im_str!("values"), MemoryView::Plain),
+                (
+                    im_str!("executed"),
+                    MemoryView::Heatmap(MemoryAccess::Execute),
+                ),
+                (im_str!("read"), MemoryView::Heatmap(MemoryAccess::Read)),
+                (im_str!("written"), MemoryView::Heatmap(MemoryAccess::Write)),
+                (im_str!("coverage"), MemoryView::Coverage),
+            ]
+            .iter()
+            {
+                ui.same_line(0.0);
+                ui.radio_button(label, &mut gui_state.memory_view, *view);
+            }
+            ui.checkbox(im_str!("profile"), &mut profiler.enabled);
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Reset")) {
+                profiler.clear();
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Export")) {
+                match profiler.save_report(chip8_state) {
+                    Ok(path) => println!("Profile written to {}", path),
+                    Err(error) => println!("Error with writing profile: {}", error),
+                }
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Export Coverage")) {
+                match save_coverage(profiler) {
+                    Ok(path) => println!("Coverage written to {}", path),
+                    Err(error) => println!("Error with writing coverage: {}", error),
+                }
+            }
+            ui.same_line(0.0);
+            ui.text(im_str!("{} instructions", profiler.total_cycles()));
+            ui.separator();
+
+            let heatmap_counts = match gui_state.memory_view {
+                MemoryView::Heatmap(access) => Some(profiler.counts(access)),
+                _ => None,
+            };
+            let max_count = heatmap_counts
+                .and_then(|counts| counts.iter().max().copied())
+                .unwrap_or(0);
+
+            let col_count = (area.size[0] as usize / 22).max(1);
             let table_count = chip8_state.memory.len() / col_count;
 
             for i in 0..table_count {
@@ -70,16 +416,126 @@ fn render_memory_table(ui: &Ui, chip8_state: &Chip8VM, flags: WindowFlags) {
                     let index = (col_count * i + j) as u16;
                     let text = im_str!("{:02X?}", chip8_state.memory[col_count * i + j]);
 
-                    if index == chip8_state.program_counter
+                    if index == gui_state.selected_memory_address {
+                        ui.text_colored([0.0, 1.0, 0.5, 1.0], text);
+                    } else if index == chip8_state.program_counter
                         || index == chip8_state.program_counter + 1
                     {
                         ui.text_colored([1.0, 0.0, 0.5, 1.0], text);
+                    } else if let Some(counts) = heatmap_counts {
+                        ui.text_colored(heatmap_color(counts[index as usize], max_count), text);
+                    } else if gui_state.memory_view == MemoryView::Coverage {
+                        ui.text_colored(coverage_color(coverage(profiler, index)), text);
                     } else {
                         ui.text(text);
                     }
+                    if ui.is_item_clicked(MouseButton::Left) {
+                        gui_state.selected_memory_address = index;
+                    }
+                    if gui_state.memory_view != MemoryView::Plain && ui.is_item_hovered() {
+                        let address = index as usize;
+                        ui.tooltip_text(format!(
+                            "{:03X?}  executed: {}  read: {}  written: {}",
+                            index,
+                            profiler.counts(MemoryAccess::Execute)[address],
+                            profiler.counts(MemoryAccess::Read)[address],
+                            profiler.counts(MemoryAccess::Write)[address]
+                        ));
+                    }
                     ui.same_line(0.0);
                 }
                 ui.dummy([0.0, 0.0]);
             }
         });
 }
+
+fn render_trace_window(
+    ui: &Ui,
+    area: Area,
+    tracer: &mut Tracer,
+    gui_state: &mut GuiState,
+    flags: WindowFlags,
+) {
+    Window::new(im_str!("Trace"))
+        .size(area.size, imgui::Condition::Always)
+        .position(area.position, imgui::Condition::Always)
+        .flags(flags)
+        .build(ui, || {
+            ui.checkbox(im_str!("enabled"), &mut tracer.enabled);
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Clear")) {
+                tracer.clear();
+            }
+            ui.same_line(0.0);
+            ui.set_next_item_width(-1.0);
+            ui.input_text(im_str!("##filter"), &mut gui_state.trace_filter)
+                .resize_buffer(true)
+                .build();
+            ui.separator();
+
+            ChildWindow::new("trace_entries")
+                .horizontal_scrollbar(true)
+                .build(ui, || {
+                    let filter = gui_state.trace_filter.to_str().to_uppercase();
+                    for entry in tracer.entries() {
+                        let line = entry.to_string();
+                        if filter.is_empty() || line.to_uppercase().contains(filter.as_str()) {
+                            ui.text(line);
+                        }
+                    }
+
+                    // keep following new entries unless the user scrolled up
+                    if ui.scroll_y() >= ui.scroll_max_y() {
+                        ui.set_scroll_here_y_with_ratio(1.0);
+                    }
+                });
+        });
+}
+
+fn render_call_stack_window(
+    ui: &Ui,
+    area: Area,
+    chip8_state: &Chip8VM,
+    symbols: &SymbolTable,
+    gui_state: &mut GuiState,
+    flags: WindowFlags,
+) {
+    const STACK_WARNING_DEPTH: usize = STACK_SIZE - 2;
+
+    Window::new(im_str!("Call Stack"))
+        .size(area.size, imgui::Condition::Always)
+        .position(area.position, imgui::Condition::Always)
+        .flags(flags)
+        .build(ui, || {
+            let depth = chip8_state.stack_pointer as usize;
+            ui.text(im_str!("depth: {} / {}", depth, STACK_SIZE));
+            if depth >= STACK_SIZE {
+                ui.text_colored([1.0, 0.0, 0.0, 1.0], im_str!("Stack is full!"));
+            } else if depth >= STACK_WARNING_DEPTH {
+                ui.text_colored([1.0, 0.7, 0.0, 1.0], im_str!("Stack is almost full!"));
+            }
+            ui.separator();
+
+            for (level, frame) in call_stack(chip8_state).iter().enumerate() {
+                let text = im_str!(
+                    "#{:<2} {} [{:04X?}]  ret {:03X?}",
+                    level,
+                    symbols.label(frame.entry_address),
+                    frame.call_opcode,
+                    frame.return_address
+                );
+                if Selectable::new(&text).build(ui) {
+                    gui_state.disassembly_address = Some(frame.entry_address);
+                }
+                if ui.is_item_clicked(MouseButton::Right) {
+                    gui_state.disassembly_address = Some(frame.call_address);
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(format!(
+                        "Called from {:03X?}\nClick to view subroutine, right click to view caller",
+                        frame.call_address
+                    ));
+                }
+            }
+        });
+}
@@ -14,8 +14,10 @@ use imgui_gfx_renderer::*;
 use std::time::Instant;
 
 use super::super::super::chip8_vm::*;
+use super::super::super::display::Layout;
+use super::super::{Debugger, Instruments};
 
-use super::imgui_render::render_gui;
+use super::imgui_render::{render_gui, GuiState};
 
 #[derive(Copy, Clone, PartialEq, Debug, Default)]
 struct MouseState {
@@ -30,6 +32,7 @@ pub struct ImGuiWrapper {
     pub renderer: Renderer<gfx_core::format::Rgba8, gfx_device_gl::Resources>,
     last_frame: Instant,
     mouse_state: MouseState,
+    gui_state: GuiState,
 }
 
 impl ImGuiWrapper {
@@ -91,10 +94,19 @@ impl ImGuiWrapper {
             renderer,
             last_frame: Instant::now(),
             mouse_state: MouseState::default(),
+            gui_state: GuiState::default(),
         }
     }
 
-    pub fn render(&mut self, ctx: &mut Context, hidpi_factor: f32, chip8_state: &Chip8VM) {
+    pub fn render(
+        &mut self,
+        ctx: &mut Context,
+        hidpi_factor: f32,
+        layout: &Layout,
+        chip8_state: &mut Chip8VM,
+        debugger: &mut Debugger,
+        instruments: &mut Instruments,
+    ) {
         // Update mouse
         self.update_mouse();
 
@@ -113,7 +125,14 @@ impl ImGuiWrapper {
 
         // Various ui things
         {
-            render_gui(&ui, chip8_state);
+            render_gui(
+                &ui,
+                layout,
+                chip8_state,
+                debugger,
+                instruments,
+                &mut self.gui_state,
+            );
         }
 
         // Render
@@ -129,6 +148,10 @@ impl ImGuiWrapper {
             .unwrap();
     }
 
+    pub fn wants_keyboard(&self) -> bool {
+        self.imgui.io().want_capture_keyboard
+    }
+
     fn update_mouse(&mut self) {
         self.imgui.io_mut().mouse_pos =
             [self.mouse_state.pos.0 as f32, self.mouse_state.pos.1 as f32];
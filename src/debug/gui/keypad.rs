@@ -0,0 +1,91 @@
+use imgui::*;
+
+use super::super::super::chip8_vm::*;
+
+const BUTTON_SIZE: f32 = 40.0;
+
+// The COSMAC VIP keypad and the keyboard keys mapped to it
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+const KEYBOARD_LAYOUT: [[&str; 4]; 4] = [
+    ["1", "2", "3", "4"],
+    ["Q", "W", "E", "R"],
+    ["A", "S", "D", "F"],
+    ["Z", "X", "C", "V"],
+];
+
+const PRESSED_COLOR: [f32; 4] = [0.2, 0.7, 0.3, 1.0];
+
+#[derive(Default)]
+pub struct KeypadState {
+    pub opened: bool,
+    // key held down with the mouse, released again with the mouse button
+    mouse_key: Option<u8>,
+}
+
+pub fn render_keypad_window(ui: &Ui, chip8_state: &mut Chip8VM, state: &mut KeypadState) {
+    if !state.opened {
+        return;
+    }
+
+    let mut opened = state.opened;
+    Window::new(im_str!("Keypad"))
+        .size([200.0, 260.0], Condition::FirstUseEver)
+        .position([330.0, 16.0], Condition::FirstUseEver)
+        .opened(&mut opened)
+        .build(ui, || {
+            let mut held_key = None;
+
+            for (row, keys) in KEYPAD_LAYOUT.iter().enumerate() {
+                for (column, &key) in keys.iter().enumerate() {
+                    if column > 0 {
+                        ui.same_line(0.0);
+                    }
+
+                    let color = if chip8_state.pressed_key == Some(key) {
+                        Some(ui.push_style_color(StyleColor::Button, PRESSED_COLOR))
+                    } else {
+                        None
+                    };
+                    ui.button(&im_str!("{:X?}", key), [BUTTON_SIZE, BUTTON_SIZE]);
+                    if let Some(color) = color {
+                        color.pop(ui);
+                    }
+
+                    if ui.is_item_active() {
+                        held_key = Some(key);
+                    }
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text(format!("keyboard: {}", KEYBOARD_LAYOUT[row][column]));
+                    }
+                }
+            }
+
+            if held_key.is_some() {
+                chip8_state.pressed_key = held_key;
+            } else if state.mouse_key.is_some() && chip8_state.pressed_key == state.mouse_key {
+                chip8_state.pressed_key = None;
+            }
+            state.mouse_key = held_key;
+
+            ui.separator();
+            match chip8_state.pressed_key {
+                Some(key) => ui.text(im_str!("pressed: {:X?}", key)),
+                None => ui.text(im_str!("pressed: none")),
+            }
+            if chip8_state.waiting_for_key_press {
+                ui.text_colored(
+                    [1.0, 0.7, 0.0, 1.0],
+                    im_str!(
+                        "FX0A waiting, stores into V{:X?}",
+                        chip8_state.key_index_store
+                    ),
+                );
+            }
+        });
+    state.opened = opened;
+}
@@ -0,0 +1,99 @@
+use imgui::*;
+
+use super::super::super::chip8_vm::*;
+use super::super::SymbolTable;
+use super::imgui_render::input_hex;
+
+#[derive(Default)]
+pub struct LabelsState {
+    pub opened: bool,
+    address: u16,
+    name: ImString,
+    comment: ImString,
+    // result of the last add or save, errors included
+    status: String,
+}
+
+// Returns the address of a label the user clicked, to show it in the disassembly
+pub fn render_labels_window(
+    ui: &Ui,
+    chip8_state: &Chip8VM,
+    symbols: &mut SymbolTable,
+    state: &mut LabelsState,
+) -> Option<u16> {
+    if !state.opened {
+        return None;
+    }
+
+    let mut selected_address = None;
+    let mut opened = state.opened;
+    Window::new(im_str!("Labels"))
+        .size([300.0, 360.0], Condition::FirstUseEver)
+        .position([540.0, 16.0], Condition::FirstUseEver)
+        .opened(&mut opened)
+        .build(ui, || {
+            if let Some(address) = input_hex(ui, im_str!("address"), state.address, 3) {
+                if (address as usize) < MEMORY_SIZE {
+                    state.address = address;
+                }
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("At PC")) {
+                state.address = chip8_state.program_counter;
+            }
+            ui.input_text(im_str!("name"), &mut state.name)
+                .resize_buffer(true)
+                .build();
+            ui.input_text(im_str!("comment"), &mut state.comment)
+                .resize_buffer(true)
+                .build();
+            if ui.small_button(im_str!("Add")) {
+                let comment = Some(state.comment.to_str());
+                state.status = match symbols.insert(state.address, state.name.to_str(), comment) {
+                    Ok(()) => {
+                        state.name.clear();
+                        state.comment.clear();
+                        format!("Labeled {:03X?}", state.address)
+                    }
+                    Err(error) => error,
+                };
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Save")) {
+                state.status = match symbols.save() {
+                    Ok(path) => format!("Saved to {}", path),
+                    Err(error) => error,
+                };
+            }
+            if let Some(path) = &symbols.path {
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(format!("Write the labels to {}", path));
+                }
+            }
+            ui.text(&state.status);
+            ui.separator();
+
+            let mut removed_address = None;
+            ChildWindow::new("label_list").build(ui, || {
+                for (&address, symbol) in symbols.iter() {
+                    if ui.small_button(&im_str!("x##{}", address)) {
+                        removed_address = Some(address);
+                    }
+                    ui.same_line(0.0);
+                    if Selectable::new(&im_str!("{:03X?} {}", address, symbol.name)).build(ui) {
+                        selected_address = Some(address);
+                    }
+                    if let Some(comment) = &symbol.comment {
+                        if ui.is_item_hovered() {
+                            ui.tooltip_text(comment);
+                        }
+                    }
+                }
+            });
+            if let Some(address) = removed_address {
+                symbols.remove(address);
+            }
+        });
+    state.opened = opened;
+    selected_address
+}
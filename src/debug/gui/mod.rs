@@ -1,4 +1,8 @@
 mod imgui_render;
 mod imgui_wrapper;
+mod keypad;
+mod labels;
+mod pixel_inspector;
+mod sprite_viewer;
 
 pub use imgui_wrapper::ImGuiWrapper;
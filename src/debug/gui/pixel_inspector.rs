@@ -0,0 +1,76 @@
+use imgui::*;
+
+use super::super::super::chip8_vm::*;
+use super::super::super::display::Layout;
+use super::super::SymbolTable;
+
+#[derive(Default)]
+pub struct PixelInspectorState {
+    // display coordinates of the last pixel clicked in the game display
+    pixel: Option<(usize, usize)>,
+}
+
+// The game display has no imgui window over it, so clicks imgui doesn't
+// want for itself landed on a pixel
+fn clicked_pixel(ui: &Ui, layout: &Layout) -> Option<(usize, usize)> {
+    if ui.io().want_capture_mouse || !ui.is_mouse_clicked(MouseButton::Left) {
+        return None;
+    }
+    layout.pixel_at(ui.io().mouse_pos)
+}
+
+// Returns the address of the drawing instruction when the user asks to see it
+pub fn render_pixel_inspector_window(
+    ui: &Ui,
+    layout: &Layout,
+    chip8_state: &Chip8VM,
+    symbols: &SymbolTable,
+    state: &mut PixelInspectorState,
+) -> Option<u16> {
+    if let Some(pixel) = clicked_pixel(ui, layout) {
+        state.pixel = Some(pixel);
+    }
+    let (x, y) = state.pixel?;
+
+    let mut selected_address = None;
+    let mut opened = true;
+    Window::new(im_str!("Pixel"))
+        .size([240.0, 160.0], Condition::FirstUseEver)
+        .position([16.0, 300.0], Condition::FirstUseEver)
+        .opened(&mut opened)
+        .build(ui, || {
+            let lit = chip8_state.display_data[y * DISPLAY_SIZE[0] + x];
+            ui.text(im_str!(
+                "x: {}  y: {}  {}",
+                x,
+                y,
+                if lit { "on" } else { "off" }
+            ));
+            ui.separator();
+
+            match chip8_state.pixel_source(x, y) {
+                Some(source) => {
+                    ui.text(im_str!("last toggled by DRW at cycle {}", source.cycle));
+                    ui.text(im_str!(
+                        "PC: {:03X?} ({})",
+                        source.program_counter,
+                        symbols.label(source.program_counter)
+                    ));
+                    ui.text(im_str!("I: {:03X?}", source.i));
+                    if source.collision {
+                        ui.text_colored([1.0, 0.7, 0.0, 1.0], im_str!("collision, VF = 1"));
+                    } else {
+                        ui.text(im_str!("no collision, VF = 0"));
+                    }
+                    if ui.small_button(im_str!("Show in disassembly")) {
+                        selected_address = Some(source.program_counter);
+                    }
+                }
+                None => ui.text(im_str!("no recorded DRW toggled it since the last CLS")),
+            }
+        });
+    if !opened {
+        state.pixel = None;
+    }
+    selected_address
+}
@@ -0,0 +1,154 @@
+use imgui::*;
+
+use super::super::super::chip8_vm::*;
+use super::super::super::fonts_sprites::FONTS_SPRITES;
+use super::imgui_render::input_hex;
+
+const PIXEL_SIZE: f32 = 6.0;
+const FONT_PIXEL_SIZE: f32 = 4.0;
+const BROWSER_ROW_COUNT: u16 = 32;
+
+const PIXEL_ON_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const PIXEL_OFF_COLOR: [f32; 4] = [0.15, 0.15, 0.15, 1.0];
+
+pub struct SpriteViewerState {
+    pub opened: bool,
+    sprite_height: u8,
+    browser_address: u16,
+}
+
+impl Default for SpriteViewerState {
+    fn default() -> Self {
+        Self {
+            opened: false,
+            sprite_height: 5,
+            browser_address: CHIP8_RESERVED_MEMORY_SIZE as u16,
+        }
+    }
+}
+
+// Draws sprite rows at the cursor the way DXYN would, one byte per row
+fn draw_sprite(ui: &Ui, rows: &[u8], pixel_size: f32) {
+    let [x, y] = ui.cursor_screen_pos();
+    {
+        let draw_list = ui.get_window_draw_list();
+        for (row_index, row) in rows.iter().enumerate() {
+            for bit in 0..8 {
+                let color = if row & (0x80 >> bit) != 0 {
+                    PIXEL_ON_COLOR
+                } else {
+                    PIXEL_OFF_COLOR
+                };
+                let top_left = [
+                    x + bit as f32 * pixel_size,
+                    y + row_index as f32 * pixel_size,
+                ];
+                draw_list
+                    .add_rect(
+                        top_left,
+                        [top_left[0] + pixel_size, top_left[1] + pixel_size],
+                        color,
+                    )
+                    .filled(true)
+                    .build();
+            }
+        }
+    }
+    ui.dummy([8.0 * pixel_size, rows.len() as f32 * pixel_size]);
+}
+
+fn memory_rows(chip8_state: &Chip8VM, address: u16, count: usize) -> &[u8] {
+    let start = (address as usize).min(MEMORY_SIZE);
+    let end = (start + count).min(MEMORY_SIZE);
+    &chip8_state.memory[start..end]
+}
+
+pub fn render_sprite_viewer_window(
+    ui: &Ui,
+    chip8_state: &mut Chip8VM,
+    state: &mut SpriteViewerState,
+) {
+    if !state.opened {
+        return;
+    }
+
+    let mut opened = state.opened;
+    Window::new(im_str!("Sprites"))
+        .size([300.0, 480.0], Condition::FirstUseEver)
+        .position([16.0, 16.0], Condition::FirstUseEver)
+        .opened(&mut opened)
+        .build(ui, || {
+            ui.text(im_str!("Sprite at I ({:03X?}):", chip8_state.i));
+            ui.set_next_item_width(120.0);
+            Slider::new(im_str!("height"), 1..=15).build(ui, &mut state.sprite_height);
+            draw_sprite(
+                ui,
+                memory_rows(chip8_state, chip8_state.i, state.sprite_height as usize),
+                PIXEL_SIZE,
+            );
+            ui.separator();
+
+            ui.text(im_str!("Font:"));
+            for (digit, glyph) in FONTS_SPRITES.iter().enumerate() {
+                if digit % 8 != 0 {
+                    ui.same_line(0.0);
+                }
+                let address = (digit * glyph.len()) as u16;
+                draw_sprite(
+                    ui,
+                    memory_rows(chip8_state, address, glyph.len()),
+                    FONT_PIXEL_SIZE,
+                );
+                if ui.is_item_hovered() {
+                    let changed = memory_rows(chip8_state, address, glyph.len()) != &glyph[..];
+                    ui.tooltip_text(format!(
+                        "{:X?} at {:03X?}{}",
+                        digit,
+                        address,
+                        if changed { " (overwritten)" } else { "" }
+                    ));
+                }
+            }
+            ui.separator();
+
+            ui.text(im_str!("Memory as sprites:"));
+            if let Some(address) = input_hex(ui, im_str!("##browser"), state.browser_address, 3) {
+                if (address as usize) < MEMORY_SIZE {
+                    state.browser_address = address;
+                }
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("At I")) {
+                state.browser_address = chip8_state.i;
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("<")) {
+                state.browser_address = state.browser_address.saturating_sub(BROWSER_ROW_COUNT);
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!(">")) {
+                state.browser_address =
+                    (state.browser_address + BROWSER_ROW_COUNT).min((MEMORY_SIZE - 1) as u16);
+            }
+
+            ChildWindow::new("sprite_browser").build(ui, || {
+                for row in 0..BROWSER_ROW_COUNT {
+                    let address = state.browser_address + row;
+                    if address as usize >= MEMORY_SIZE {
+                        break;
+                    }
+                    let value = chip8_state.memory[address as usize];
+                    ui.text(im_str!("{:03X?}: {:02X?}", address, value));
+                    ui.same_line(0.0);
+                    draw_sprite(ui, &[value], PIXEL_SIZE);
+                    if ui.is_item_clicked(MouseButton::Left) {
+                        chip8_state.i = address;
+                    }
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Click to point I here");
+                    }
+                }
+            });
+        });
+    state.opened = opened;
+}
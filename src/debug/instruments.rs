@@ -0,0 +1,29 @@
+use crate::chip8_vm::Chip8VM;
+
+use super::{Profiler, RegisterSnapshot, SymbolTable, Tracer};
+
+// Everything that watches the VM while it runs, fed after every instruction
+pub struct Instruments {
+    pub tracer: Tracer,
+    pub profiler: Profiler,
+}
+
+impl Instruments {
+    pub fn new(tracer: Tracer, profiler: Profiler) -> Self {
+        Self { tracer, profiler }
+    }
+
+    pub fn record(
+        &mut self,
+        program_counter: u16,
+        opcode: u16,
+        before: &RegisterSnapshot,
+        chip8_state: &Chip8VM,
+        symbols: &SymbolTable,
+    ) {
+        self.tracer
+            .record(program_counter, opcode, before, chip8_state, symbols);
+        self.profiler
+            .record(program_counter, opcode, before, chip8_state);
+    }
+}
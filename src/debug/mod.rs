@@ -1,2 +1,28 @@
+mod coverage;
+mod cycle;
+mod dap_server;
+mod debugger;
+mod gdb_stub;
 mod gui;
+mod instruments;
+mod profiler;
+mod remote;
+mod repl;
+mod source_map;
+mod symbols;
+mod trace;
+pub use coverage::{
+    coverage, coverage_regions, coverage_report, save_coverage, Coverage, CoverageRegion,
+};
+pub use cycle::run_cycle;
+pub use dap_server::{decode_message, encode_message, DapServer, DapSession};
+pub use debugger::{call_stack, CallFrame, Debugger, RunMode, UnknownOpcodeAction};
+pub use gdb_stub::{encode_packet, GdbStub};
 pub use gui::ImGuiWrapper;
+pub use instruments::Instruments;
+pub use profiler::{MemoryAccess, Profiler, SubroutineProfile};
+pub use remote::RemoteDebuggers;
+pub use repl::{Repl, ReplOutput};
+pub use source_map::{SourceLocation, SourceMap};
+pub use symbols::{default_symbol_path, SymbolTable};
+pub use trace::{RegisterChange, RegisterSnapshot, TraceEntry, Tracer, V_REGISTER_NAMES};
@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+
+use crate::chip8_vm::{fetch_opcode, Chip8VM, CHIP8_RESERVED_MEMORY_SIZE, MEMORY_SIZE};
+use crate::instructions::{decode_opcode, InstructionSet};
+
+use super::RegisterSnapshot;
+
+// Code outside of any subroutine is accounted to the ROM entry point
+pub const MAIN_ENTRY_ADDRESS: u16 = CHIP8_RESERVED_MEMORY_SIZE as u16;
+
+const REPORT_SIZE: usize = 16;
+const DEFAULT_REPORT_PATH: &str = "profile.txt";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MemoryAccess {
+    Execute,
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SubroutineProfile {
+    pub entry_address: u16,
+    pub calls: u64,
+    // instructions executed in the subroutine itself, not in the ones it calls
+    pub cycles: u64,
+}
+
+// Bytes of memory an instruction reads or writes, apart from fetching it
+pub fn memory_accesses(
+    instruction: InstructionSet,
+    before: &RegisterSnapshot,
+) -> Option<(MemoryAccess, u16, usize)> {
+    match instruction {
+        InstructionSet::DrawSprite(_, _, height) => {
+            Some((MemoryAccess::Read, before.i, height as usize))
+        }
+        InstructionSet::FillRegistersV0ToVxInclusiveWithMemoryStartingAtAddressI(index) => {
+            Some((MemoryAccess::Read, before.i, index as usize + 1))
+        }
+        InstructionSet::StoreValuesOfV0ToVxInclusiveInMemoryStartingAtAddressI(index) => {
+            Some((MemoryAccess::Write, before.i, index as usize + 1))
+        }
+        InstructionSet::StoreTheBinaryCodedDecimalEquivalentOfVx(_) => {
+            Some((MemoryAccess::Write, before.i, 3))
+        }
+        _ => None,
+    }
+}
+
+pub struct Profiler {
+    pub enabled: bool,
+    pub report_path: Option<String>,
+    pub coverage_path: Option<String>,
+    total_cycles: u64,
+    execution_counts: Vec<u64>,
+    read_counts: Vec<u64>,
+    write_counts: Vec<u64>,
+    subroutines: BTreeMap<u16, SubroutineProfile>,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            report_path: None,
+            coverage_path: None,
+            total_cycles: 0,
+            execution_counts: vec![0; MEMORY_SIZE],
+            read_counts: vec![0; MEMORY_SIZE],
+            write_counts: vec![0; MEMORY_SIZE],
+            subroutines: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_report_file(&mut self, path: &str) {
+        self.report_path = Some(path.to_string());
+        self.enabled = true;
+    }
+
+    pub fn set_coverage_file(&mut self, path: &str) {
+        self.coverage_path = Some(path.to_string());
+        self.enabled = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.total_cycles = 0;
+        for counts in [
+            &mut self.execution_counts,
+            &mut self.read_counts,
+            &mut self.write_counts,
+        ]
+        .iter_mut()
+        {
+            counts.iter_mut().for_each(|count| *count = 0);
+        }
+        self.subroutines.clear();
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn counts(&self, access: MemoryAccess) -> &[u64] {
+        match access {
+            MemoryAccess::Execute => &self.execution_counts,
+            MemoryAccess::Read => &self.read_counts,
+            MemoryAccess::Write => &self.write_counts,
+        }
+    }
+
+    fn subroutine(&mut self, entry_address: u16) -> &mut SubroutineProfile {
+        self.subroutines
+            .entry(entry_address)
+            .or_insert(SubroutineProfile {
+                entry_address,
+                calls: 0,
+                cycles: 0,
+            })
+    }
+
+    // Called after every executed instruction with the registers from before it ran
+    pub fn record(
+        &mut self,
+        program_counter: u16,
+        opcode: u16,
+        before: &RegisterSnapshot,
+        chip8_state: &Chip8VM,
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        self.total_cycles += 1;
+        let address = program_counter as usize;
+        self.execution_counts[address] += 1;
+        self.execution_counts[address + 1] += 1;
+
+        // a CALL only pushes above the current frame, so the return address
+        // of the frame the instruction ran in is still on the stack
+        let entry_address = match before.stack_pointer as usize {
+            0 => MAIN_ENTRY_ADDRESS,
+            stack_pointer => {
+                let call_address = chip8_state.stack[stack_pointer - 1];
+                fetch_opcode(&chip8_state.memory, call_address).unwrap_or(0) & 0x0FFF
+            }
+        };
+        self.subroutine(entry_address).cycles += 1;
+
+        let instruction = decode_opcode(opcode);
+        if let InstructionSet::ExecuteSubroutine(address) = instruction {
+            self.subroutine(address).calls += 1;
+        }
+
+        if let Some((access, start, length)) = memory_accesses(instruction, before) {
+            let counts = match access {
+                MemoryAccess::Write => &mut self.write_counts,
+                _ => &mut self.read_counts,
+            };
+            let start = start as usize;
+            for count in counts.iter_mut().skip(start).take(length) {
+                *count += 1;
+            }
+        }
+    }
+
+    // Subroutines sorted by the instructions they executed, busiest first
+    pub fn hottest_subroutines(&self) -> Vec<SubroutineProfile> {
+        let mut subroutines: Vec<SubroutineProfile> = self.subroutines.values().copied().collect();
+        subroutines.sort_by(|a, b| {
+            b.cycles
+                .cmp(&a.cycles)
+                .then(a.entry_address.cmp(&b.entry_address))
+        });
+        subroutines
+    }
+
+    // Instruction addresses sorted by execution count, busiest first
+    pub fn hottest_instructions(&self) -> Vec<(u16, u64)> {
+        let mut instructions: Vec<(u16, u64)> = self
+            .execution_counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(address, &count)| (address as u16, count))
+            .collect();
+        instructions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        // both bytes of an instruction have the same count, keep the first one
+        let mut previous: Option<(u16, u64)> = None;
+        instructions.retain(|&(address, count)| {
+            if previous == Some((address.wrapping_sub(1), count)) {
+                previous = None;
+                false
+            } else {
+                previous = Some((address, count));
+                true
+            }
+        });
+        instructions
+    }
+
+    pub fn report(&self, chip8_state: &Chip8VM) -> String {
+        let percent = |cycles: u64| 100.0 * cycles as f64 / self.total_cycles.max(1) as f64;
+        let mut report = String::new();
+
+        writeln!(report, "Profile of {} instructions", self.total_cycles).unwrap();
+        writeln!(report).unwrap();
+        writeln!(report, "Hottest subroutines:").unwrap();
+        writeln!(report, "  address     calls    instructions       %").unwrap();
+        for subroutine in self.hottest_subroutines().iter().take(REPORT_SIZE) {
+            let name = match subroutine.entry_address {
+                MAIN_ENTRY_ADDRESS if subroutine.calls == 0 => "main".to_string(),
+                address => format!("{:03X?}", address),
+            };
+            writeln!(
+                report,
+                "  {:<8} {:>8} {:>15} {:>6.1}%",
+                name,
+                subroutine.calls,
+                subroutine.cycles,
+                percent(subroutine.cycles)
+            )
+            .unwrap();
+        }
+
+        writeln!(report).unwrap();
+        writeln!(report, "Hottest instructions:").unwrap();
+        writeln!(report, "  address    count       %  instruction").unwrap();
+        for &(address, count) in self.hottest_instructions().iter().take(REPORT_SIZE) {
+            let opcode = fetch_opcode(&chip8_state.memory, address).unwrap_or(0);
+            let instruction = decode_opcode(opcode);
+            let note = match instruction {
+                InstructionSet::StoreDelayTimerInRegisterVx(_) => "  <- delay timer poll",
+                _ => "",
+            };
+            writeln!(
+                report,
+                "  {:03X?}     {:>8} {:>6.1}%  {}{}",
+                address,
+                count,
+                percent(count),
+                instruction.mnemonic(),
+                note
+            )
+            .unwrap();
+        }
+
+        report
+    }
+
+    // Writes the report to the --profile file, or profile.txt without one
+    pub fn save_report(&self, chip8_state: &Chip8VM) -> std::io::Result<String> {
+        let path = self
+            .report_path
+            .clone()
+            .unwrap_or_else(|| DEFAULT_REPORT_PATH.to_string());
+        fs::write(&path, self.report(chip8_state))?;
+        Ok(path)
+    }
+}
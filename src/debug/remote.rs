@@ -0,0 +1,123 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::chip8_vm::Chip8VM;
+
+use super::{DapServer, Debugger, GdbStub};
+
+// Debugger frontends the VM serves over TCP
+#[derive(Default)]
+pub struct RemoteDebuggers {
+    pub gdb_stub: Option<GdbStub>,
+    pub dap_server: Option<DapServer>,
+}
+
+impl RemoteDebuggers {
+    pub fn is_active(&self) -> bool {
+        self.gdb_stub.is_some() || self.dap_server.is_some()
+    }
+
+    pub fn poll(&mut self, chip8_state: &mut Chip8VM, debugger: &mut Debugger) {
+        if let Some(gdb_stub) = &mut self.gdb_stub {
+            gdb_stub.poll(chip8_state, debugger);
+        }
+        if let Some(dap_server) = &mut self.dap_server {
+            dap_server.poll(chip8_state, debugger);
+        }
+    }
+}
+
+// A client of a RemoteListener, with the protocol's state for it
+pub struct RemoteConnection<S> {
+    stream: TcpStream,
+    // received bytes the protocol hasn't used yet
+    pub input: Vec<u8>,
+    pub state: S,
+}
+
+impl<S> RemoteConnection<S> {
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.write_all(data)
+    }
+
+    fn receive(&mut self) -> io::Result<()> {
+        let mut buffer = [0_u8; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
+                Ok(size) => self.input.extend_from_slice(&buffer[..size]),
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+// Serves one client at a time, the VM is paused while one connects and
+// runs again when it leaves
+pub struct RemoteListener<S> {
+    // the protocol's name in messages, e.g. "GDB"
+    name: &'static str,
+    listener: TcpListener,
+    connection: Option<RemoteConnection<S>>,
+}
+
+impl<S: Default> RemoteListener<S> {
+    pub fn bind(name: &'static str, address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            name,
+            listener,
+            connection: None,
+        })
+    }
+
+    pub fn local_address(&self) -> io::Result<String> {
+        Ok(self.listener.local_addr()?.to_string())
+    }
+
+    // Never blocks, so it can be called once per frame from the main loop.
+    // Hands the client to serve after reading what it sent, and drops it
+    // when either fails.
+    pub fn poll<F>(&mut self, debugger: &mut Debugger, serve: F)
+    where
+        F: FnOnce(&mut RemoteConnection<S>, &mut Debugger) -> io::Result<()>,
+    {
+        if self.connection.is_none() {
+            self.accept(debugger);
+        }
+
+        let keep_connection = match &mut self.connection {
+            Some(connection) => connection
+                .receive()
+                .and_then(|()| serve(connection, debugger))
+                .is_ok(),
+            None => true,
+        };
+
+        if !keep_connection {
+            println!("{} client disconnected", self.name);
+            self.connection = None;
+            debugger.resume();
+        }
+    }
+
+    fn accept(&mut self, debugger: &mut Debugger) {
+        match self.listener.accept() {
+            Ok((stream, address)) => {
+                println!("{} client connected from {}", self.name, address);
+                if stream.set_nonblocking(true).is_ok() {
+                    debugger.pause();
+                    self.connection = Some(RemoteConnection {
+                        stream,
+                        input: Vec::new(),
+                        state: S::default(),
+                    });
+                }
+            }
+            Err(ref error) if error.kind() == ErrorKind::WouldBlock => (),
+            Err(error) => println!("Error with accepting {} client: {}", self.name, error),
+        }
+    }
+}
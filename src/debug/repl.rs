@@ -0,0 +1,598 @@
+// Terminal debugger for machines without a display. It drives the VM
+// directly, so there is no window, no sound and no timing, instructions
+// just run as fast as the commands ask for them.
+
+use std::io::{self, BufRead, Write};
+
+use crate::audio::{Beeper, BeeperSettings};
+use crate::chip8_vm::{fetch_opcode, Chip8VM, DISPLAY_SIZE, MEMORY_SIZE};
+use crate::display::{check_screenshot_scale, save_screenshot, Palette, Recorder};
+use crate::instructions::decode_opcode;
+use crate::utils::parse_address;
+
+use super::{call_stack, run_cycle, Debugger, SymbolTable, Tracer};
+
+// "continue" gives up after this many instructions, there is no other way
+// to interrupt a ROM stuck in a loop
+pub const CONTINUE_CYCLE_LIMIT: u64 = 1_000_000;
+
+const HELP: &str = "\
+break [ADDR]        set a breakpoint, or list breakpoints
+delete ADDR         remove a breakpoint
+watch [ADDR]        stop when the byte at ADDR changes, or list watchpoints
+unwatch ADDR        remove a watchpoint
+step [N]            execute N instructions (default 1)
+next                step over subroutine calls
+finish              run until the current subroutine returns
+continue            run until a breakpoint, watchpoint or key wait
+regs                show the registers
+stack               show the call stack
+x/N [ADDR]          dump N bytes of memory (default 16 bytes at I)
+disasm [ADDR] [N]   disassemble N instructions (default 10 at PC)
+set REG VALUE       set V0-VF, I, PC, DT or ST
+set [ADDR] VALUE    write a byte to memory
+key [KEY|none]      hold down a key 0-F, or release it
+pixel X Y           show which DRW last toggled the pixel at X, Y
+screenshot FILE [SCALE]  save the display as a PNG, SCALE pixels per VM pixel
+trace on|off        print every executed instruction
+label ADDR NAME [; COMMENT]  name an address
+unlabel ADDR        remove a label
+labels [save]       list labels, or write them to the symbol file
+quit                leave the debugger
+Addresses are hexadecimal or label names, values are hexadecimal, counts are decimal. An empty line repeats the last command.";
+
+pub enum ReplOutput {
+    Text(String),
+    Quit,
+    // an unknown opcode with --unknown-opcodes=fatal, ends the session with an error
+    Fatal(String),
+}
+
+pub struct Repl {
+    pub chip8_state: Chip8VM,
+    pub debugger: Debugger,
+    pub tracer: Tracer,
+    // advanced by a timer tick per instruction, so --audio-out works without real time
+    pub beeper: Beeper,
+    // sampled every 8 instructions, 50 frames per second of VM time
+    pub recorder: Recorder,
+    // colours of the screenshot command
+    pub palette: Palette,
+    last_command: String,
+}
+
+impl Repl {
+    pub fn new(mut chip8_state: Chip8VM, debugger: Debugger, tracer: Tracer) -> Self {
+        chip8_state.record_pixel_sources(true);
+        Self {
+            chip8_state,
+            debugger,
+            tracer,
+            beeper: Beeper::new(BeeperSettings::default()),
+            recorder: Recorder::default(),
+            palette: Palette::default(),
+            last_command: String::new(),
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let result = self.read_commands();
+        self.beeper.finish()?;
+        for path in self.recorder.finish().map_err(io::Error::other)? {
+            println!("Recording written to {}", path);
+        }
+        result
+    }
+
+    fn read_commands(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut stdout = io::stdout();
+
+        println!("CHIP-8 debugger, type 'help' for a list of commands");
+        println!("{}", self.location());
+
+        loop {
+            print!("(chip8) ");
+            stdout.flush()?;
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            match self.execute(&line) {
+                Ok(ReplOutput::Text(text)) => {
+                    if !text.is_empty() {
+                        println!("{}", text);
+                    }
+                }
+                Ok(ReplOutput::Quit) => return Ok(()),
+                Ok(ReplOutput::Fatal(error)) => return Err(io::Error::other(error)),
+                Err(error) => println!("Error: {}", error),
+            }
+        }
+    }
+
+    pub fn execute(&mut self, line: &str) -> Result<ReplOutput, String> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command.to_lowercase(),
+            None => return Ok(ReplOutput::Text(String::new())),
+        };
+        let arguments: Vec<&str> = words.collect();
+        let symbols = &self.debugger.symbols;
+
+        let text = match command.as_str() {
+            "help" | "h" => HELP.to_string(),
+            "quit" | "q" => return Ok(ReplOutput::Quit),
+            "break" | "b" => match arguments.first() {
+                Some(address) => {
+                    let address = symbols.resolve(address)?;
+                    self.debugger.breakpoints.insert(address);
+                    format!("Breakpoint at {}", symbols.label(address))
+                }
+                None => list_addresses("breakpoints", self.debugger.breakpoints.iter(), symbols),
+            },
+            "delete" | "d" => {
+                let address = symbols.resolve(argument(&arguments, 0)?)?;
+                let label = symbols.label(address);
+                if !self.debugger.breakpoints.remove(&address) {
+                    return Err(format!("No breakpoint at {}", label));
+                }
+                format!("Deleted breakpoint at {}", label)
+            }
+            "watch" | "w" => match arguments.first() {
+                Some(address) => {
+                    let address = parse_memory_address(address, symbols)?;
+                    let label = symbols.label(address);
+                    self.debugger.add_watchpoint(address, &self.chip8_state);
+                    format!("Watchpoint at {}", label)
+                }
+                None => list_addresses("watchpoints", self.debugger.watchpoints.keys(), symbols),
+            },
+            "unwatch" => {
+                let address = symbols.resolve(argument(&arguments, 0)?)?;
+                let label = symbols.label(address);
+                if !self.debugger.remove_watchpoint(address) {
+                    return Err(format!("No watchpoint at {}", label));
+                }
+                format!("Deleted watchpoint at {}", label)
+            }
+            "step" | "s" => {
+                let count = match arguments.first() {
+                    Some(count) => count
+                        .parse::<u64>()
+                        .map_err(|_| format!("Invalid count '{}'", count))?,
+                    None => 1,
+                };
+                let mut output = Vec::new();
+                for _ in 0..count {
+                    self.debugger.step_into();
+                    output.extend(self.run_until_stop(1));
+                    if self.debugger.watchpoint_hit.is_some()
+                        || self.is_stopped_at_breakpoint()
+                        || self.is_stopped_at_unknown_opcode()
+                        || self.chip8_state.waiting_for_key_press
+                    {
+                        break;
+                    }
+                }
+                self.stop_report(output)
+            }
+            "next" | "n" => {
+                self.debugger.step_over(&self.chip8_state);
+                let output = self.run_until_stop(CONTINUE_CYCLE_LIMIT);
+                self.stop_report(output)
+            }
+            "finish" | "f" => {
+                self.debugger.step_out(&self.chip8_state);
+                let output = self.run_until_stop(CONTINUE_CYCLE_LIMIT);
+                self.stop_report(output)
+            }
+            "continue" | "c" => {
+                self.debugger.resume();
+                let output = self.run_until_stop(CONTINUE_CYCLE_LIMIT);
+                self.stop_report(output)
+            }
+            "regs" | "r" => self.registers(),
+            "stack" | "bt" => self.stack(),
+            "disasm" => {
+                let address = match arguments.first() {
+                    Some(address) => symbols.resolve(address)?,
+                    None => self.chip8_state.program_counter,
+                };
+                let count = match arguments.get(1) {
+                    Some(count) => count
+                        .parse::<u16>()
+                        .map_err(|_| format!("Invalid count '{}'", count))?,
+                    None => 10,
+                };
+                self.disassemble(address, count)
+            }
+            "set" => self.set(&arguments)?,
+            "key" => match arguments.first() {
+                None | Some(&"none") => {
+                    self.chip8_state.pressed_key = None;
+                    "Released keys".to_string()
+                }
+                Some(key) => match u8::from_str_radix(key, 16) {
+                    Ok(key) if key <= 0xF => {
+                        self.chip8_state.pressed_key = Some(key);
+                        format!("Holding key {:X?}", key)
+                    }
+                    _ => return Err(format!("Invalid key '{}'", key)),
+                },
+            },
+            "trace" => match arguments.first() {
+                Some(&"on") => {
+                    self.tracer.enabled = true;
+                    "Tracing on".to_string()
+                }
+                Some(&"off") => {
+                    self.tracer.enabled = false;
+                    "Tracing off".to_string()
+                }
+                _ => return Err("Usage: trace on|off".to_string()),
+            },
+            "pixel" => self.pixel(&arguments)?,
+            "screenshot" => {
+                let path = argument(&arguments, 0)?;
+                let scale = match arguments.get(1) {
+                    Some(text) => match text.parse::<usize>() {
+                        Ok(scale) => check_screenshot_scale(scale)?,
+                        Err(_) => return Err(format!("Invalid scale '{}'", text)),
+                    },
+                    None => 1,
+                };
+                save_screenshot(path, &self.chip8_state.display_data, self.palette, scale)?;
+                format!("Screenshot written to {}", path)
+            }
+            "label" => self.label(&line)?,
+            "unlabel" => {
+                let address = symbols.resolve(argument(&arguments, 0)?)?;
+                match self.debugger.symbols.remove(address) {
+                    Some(symbol) => format!("Deleted label {} at {:03X?}", symbol.name, address),
+                    None => return Err(format!("No label at {:03X?}", address)),
+                }
+            }
+            "labels" => match arguments.first() {
+                Some(&"save") => format!("Saved labels to {}", symbols.save()?),
+                Some(_) => return Err("Usage: labels [save]".to_string()),
+                None if symbols.iter().next().is_none() => "No labels".to_string(),
+                None => symbols.to_text().trim_end().to_string(),
+            },
+            _ if command.starts_with("x/") || command == "x" => {
+                let count = match command.strip_prefix("x/") {
+                    Some(count) => count
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid count '{}'", count))?,
+                    None => 16,
+                };
+                let address = match arguments.first() {
+                    Some(address) => parse_memory_address(address, symbols)?,
+                    None => self.chip8_state.i,
+                };
+                self.dump_memory(address, count)
+            }
+            _ => return Err(format!("Unknown command '{}', try 'help'", command)),
+        };
+
+        self.beeper.flush();
+        match self.debugger.unknown_opcode_error(&self.chip8_state) {
+            Some(error) => Ok(ReplOutput::Fatal(error)),
+            None => Ok(ReplOutput::Text(text)),
+        }
+    }
+
+    // Runs like the main loop does, returns the trace lines printed on the way
+    fn run_until_stop(&mut self, cycle_limit: u64) -> Vec<String> {
+        let mut output = Vec::new();
+
+        let mut instructions = 0;
+        while instructions < cycle_limit && self.debugger.is_running() {
+            if self.chip8_state.waiting_for_key_press && self.chip8_state.pressed_key.is_none() {
+                self.debugger.pause();
+                output.push("Waiting for a key press, use 'key'".to_string());
+                break;
+            }
+
+            let tracer = &mut self.tracer;
+            let result = run_cycle(
+                &mut self.chip8_state,
+                &mut self.debugger,
+                &mut self.beeper,
+                &mut self.recorder,
+                |program_counter, opcode, registers, chip8_state, symbols| {
+                    tracer.record(program_counter, opcode, registers, chip8_state, symbols);
+                    if let Some(entry) = tracer.entries().back() {
+                        if entry.cycle == chip8_state.cycle_count {
+                            output.push(entry.to_string());
+                        }
+                    }
+                },
+            );
+            match result {
+                Ok(true) => {
+                    instructions += 1;
+                    if let Some(unknown) = self.debugger.unknown_opcode_skipped(&self.chip8_state) {
+                        output.push(format!(
+                            "Skipped unknown opcode {:04X?} at {:03X?}",
+                            unknown.opcode, unknown.address
+                        ));
+                    }
+                }
+                Ok(false) => (),
+                Err(_) => {
+                    self.debugger.pause();
+                    return output;
+                }
+            }
+        }
+
+        if self.debugger.is_running() {
+            self.debugger.pause();
+            output.push(format!("Still running after {} instructions", cycle_limit));
+        }
+        output
+    }
+
+    fn is_stopped_at_breakpoint(&self) -> bool {
+        self.debugger
+            .breakpoints
+            .contains(&self.chip8_state.program_counter)
+    }
+
+    fn is_stopped_at_unknown_opcode(&self) -> bool {
+        self.debugger.unknown_opcode_hit.is_some()
+    }
+
+    fn stop_report(&self, mut output: Vec<String>) -> String {
+        if let Some(unknown) = self.debugger.unknown_opcode_hit {
+            output.push(format!(
+                "Unknown opcode {:04X?} at {:03X?}",
+                unknown.opcode, unknown.address
+            ));
+        }
+        if let Some(hit) = self.debugger.watchpoint_hit {
+            output.push(format!(
+                "Watchpoint {:03X?}: {:02X?} -> {:02X?}",
+                hit.address, hit.old, hit.new
+            ));
+        }
+        if self.is_stopped_at_breakpoint() {
+            output.push(format!(
+                "Breakpoint at {}",
+                self.debugger
+                    .symbols
+                    .label(self.chip8_state.program_counter)
+            ));
+        }
+        output.push(self.location());
+        output.join("\n")
+    }
+
+    fn location(&self) -> String {
+        let program_counter = self.chip8_state.program_counter;
+        match fetch_opcode(&self.chip8_state.memory, program_counter) {
+            Ok(opcode) => format!(
+                "{:03X?}: {:04X?}  {}",
+                program_counter,
+                opcode,
+                self.debugger.symbols.mnemonic(&decode_opcode(opcode))
+            ),
+            Err(()) => format!("{:03X?}: out of memory", program_counter),
+        }
+    }
+
+    fn registers(&self) -> String {
+        let vm = &self.chip8_state;
+        let mut lines: Vec<String> =
+            vm.v.chunks(8)
+                .enumerate()
+                .map(|(row, registers)| {
+                    registers
+                        .iter()
+                        .enumerate()
+                        .map(|(index, value)| format!("V{:X?}: {:02X?}", row * 8 + index, value))
+                        .collect::<Vec<_>>()
+                        .join("  ")
+                })
+                .collect();
+        lines.push(format!(
+            "I: {:03X?}  PC: {:03X?}  SP: {:X?}  DT: {:02X?}  ST: {:02X?}  cycle: {}",
+            vm.i,
+            vm.program_counter,
+            vm.stack_pointer,
+            vm.delay_timer,
+            vm.sound_timer,
+            vm.cycle_count
+        ));
+        lines.join("\n")
+    }
+
+    fn stack(&self) -> String {
+        let frames = call_stack(&self.chip8_state);
+        if frames.is_empty() {
+            return "Call stack is empty".to_string();
+        }
+        frames
+            .iter()
+            .enumerate()
+            .map(|(level, frame)| {
+                format!(
+                    "#{} {} called from {:03X?}",
+                    level,
+                    self.debugger.symbols.label(frame.entry_address),
+                    frame.call_address
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn dump_memory(&self, address: u16, count: usize) -> String {
+        let start = address as usize;
+        let end = start.saturating_add(count).min(MEMORY_SIZE);
+        self.chip8_state.memory[start..end]
+            .chunks(8)
+            .enumerate()
+            .map(|(row, bytes)| {
+                let bytes: Vec<String> =
+                    bytes.iter().map(|byte| format!("{:02X?}", byte)).collect();
+                format!("{:03X?}: {}", start + row * 8, bytes.join(" "))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn disassemble(&self, address: u16, count: u16) -> String {
+        let symbols = &self.debugger.symbols;
+        (0..count)
+            .map(|index| address as usize + index as usize * 2)
+            .take_while(|&address| address + 1 < MEMORY_SIZE)
+            .map(|address| {
+                let opcode = fetch_opcode(&self.chip8_state.memory, address as u16).unwrap_or(0);
+                let marker = if address == self.chip8_state.program_counter as usize {
+                    "=>"
+                } else if self.debugger.breakpoints.contains(&(address as u16)) {
+                    " *"
+                } else {
+                    "  "
+                };
+                let line = format!(
+                    "{} {:03X?}: {:04X?}  {}",
+                    marker,
+                    address,
+                    opcode,
+                    symbols.mnemonic(&decode_opcode(opcode))
+                );
+                match symbols.get(address as u16) {
+                    Some(symbol) => match &symbol.comment {
+                        Some(comment) => format!("{}:  ; {}\n{}", symbol.name, comment, line),
+                        None => format!("{}:\n{}", symbol.name, line),
+                    },
+                    None => line,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn set(&mut self, arguments: &[&str]) -> Result<String, String> {
+        let target = argument(arguments, 0)?;
+        let value = parse_address(argument(arguments, 1)?)?;
+        let vm = &mut self.chip8_state;
+
+        if target.starts_with('[') && target.ends_with(']') {
+            let address =
+                parse_memory_address(&target[1..target.len() - 1], &self.debugger.symbols)?;
+            vm.memory[address as usize] = value as u8;
+            return Ok(format!("[{:03X?}] = {:02X?}", address, value as u8));
+        }
+
+        match target.to_lowercase().as_str() {
+            "i" if (value as usize) < MEMORY_SIZE => vm.i = value,
+            "pc" if (value as usize) < MEMORY_SIZE - 1 => vm.program_counter = value,
+            "dt" => vm.delay_timer = value as u8,
+            "st" => vm.sound_timer = value as u8,
+            register => match register
+                .strip_prefix('v')
+                .and_then(|index| u8::from_str_radix(index, 16).ok())
+            {
+                Some(index) if index <= 0xF && register.len() == 2 => {
+                    vm.v[index as usize] = value as u8
+                }
+                _ => return Err(format!("Can't set '{}'", target)),
+            },
+        }
+        Ok(format!("{} = {:X?}", target.to_uppercase(), value))
+    }
+
+    fn pixel(&self, arguments: &[&str]) -> Result<String, String> {
+        let coordinate = |index: usize| -> Result<usize, String> {
+            let text = argument(arguments, index)?;
+            text.parse::<usize>()
+                .map_err(|_| format!("Invalid coordinate '{}'", text))
+        };
+        let (x, y) = (coordinate(0)?, coordinate(1)?);
+        if x >= DISPLAY_SIZE[0] || y >= DISPLAY_SIZE[1] {
+            return Err(format!("Pixel {}, {} is off the display", x, y));
+        }
+
+        let state = if self.chip8_state.display_data[y * DISPLAY_SIZE[0] + x] {
+            "on"
+        } else {
+            "off"
+        };
+        Ok(match self.chip8_state.pixel_source(x, y) {
+            Some(source) => format!(
+                "Pixel {}, {} is {}, toggled by DRW at {} in cycle {} with I = {:03X?}{}",
+                x,
+                y,
+                state,
+                self.debugger.symbols.label(source.program_counter),
+                source.cycle,
+                source.i,
+                if source.collision {
+                    ", collision (VF = 1)"
+                } else {
+                    ""
+                }
+            ),
+            None => format!(
+                "Pixel {}, {} is {}, not drawn since the last CLS",
+                x, y, state
+            ),
+        })
+    }
+
+    // "label ADDR NAME [; COMMENT]", the comment may contain spaces
+    fn label(&mut self, line: &str) -> Result<String, String> {
+        let (entry, comment) = match line.find(';') {
+            Some(separator) => (&line[..separator], Some(line[separator + 1..].trim())),
+            None => (line, None),
+        };
+        let arguments: Vec<&str> = entry.split_whitespace().skip(1).collect();
+        if arguments.len() != 2 {
+            return Err("Usage: label ADDR NAME [; COMMENT]".to_string());
+        }
+
+        let symbols = &mut self.debugger.symbols;
+        let address = parse_memory_address(arguments[0], symbols)?;
+        symbols.insert(address, arguments[1], comment)?;
+        Ok(format!("{:03X?} is now {}", address, arguments[1]))
+    }
+}
+
+fn argument<'a>(arguments: &[&'a str], index: usize) -> Result<&'a str, String> {
+    arguments
+        .get(index)
+        .copied()
+        .ok_or_else(|| "Missing argument, try 'help'".to_string())
+}
+
+fn parse_memory_address(text: &str, symbols: &SymbolTable) -> Result<u16, String> {
+    match symbols.resolve(text)? {
+        address if (address as usize) < MEMORY_SIZE => Ok(address),
+        address => Err(format!("Address {:X?} is out of memory", address)),
+    }
+}
+
+fn list_addresses<'a>(
+    name: &str,
+    addresses: impl Iterator<Item = &'a u16>,
+    symbols: &SymbolTable,
+) -> String {
+    let addresses: Vec<String> = addresses.map(|&address| symbols.label(address)).collect();
+    if addresses.is_empty() {
+        format!("No {}", name)
+    } else {
+        addresses.join(" ")
+    }
+}
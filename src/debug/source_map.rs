@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::utils::parse_address;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SourceLocation {
+    pub path: String,
+    pub line: usize,
+}
+
+// Maps instruction addresses to assembler source lines. The file has one
+// entry per line in the form "<address> <path>:<line>", e.g. "0x2A4 pong.8o:17".
+// Empty lines and lines starting with '#' are ignored.
+#[derive(Default)]
+pub struct SourceMap {
+    locations: BTreeMap<u16, SourceLocation>,
+}
+
+impl SourceMap {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut locations = BTreeMap::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = || format!("Invalid source map entry in line {}: '{}'", index + 1, line);
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let address = parse_address(parts.next().unwrap_or(""))?;
+            let location = parts.next().ok_or_else(error)?.trim();
+            let separator = location.rfind(':').ok_or_else(error)?;
+            let source_line = location[separator + 1..]
+                .parse::<usize>()
+                .map_err(|_| error())?;
+
+            locations.insert(
+                address,
+                SourceLocation {
+                    path: location[..separator].to_string(),
+                    line: source_line,
+                },
+            );
+        }
+
+        Ok(Self { locations })
+    }
+
+    pub fn location(&self, address: u16) -> Option<&SourceLocation> {
+        self.locations.get(&address)
+    }
+
+    // Editors send absolute paths while the map usually holds relative ones
+    pub fn addresses(&self, path: &str, line: usize) -> Vec<u16> {
+        self.locations
+            .iter()
+            .filter(|(_, location)| {
+                location.line == line && Path::new(path).ends_with(&location.path)
+            })
+            .map(|(&address, _)| address)
+            .collect()
+    }
+}
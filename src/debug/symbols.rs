@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::instructions::InstructionSet;
+use crate::utils::parse_address;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub comment: Option<String>,
+}
+
+// Names for addresses, loaded from and saved to a symbol file with one
+// entry per line in the form "<address> <name> [; comment]", e.g.
+// "0x2A4 draw_paddle ; V0, V1 = paddle position". Empty lines and lines
+// starting with '#' are ignored.
+#[derive(Default)]
+pub struct SymbolTable {
+    pub path: Option<String>,
+    symbols: BTreeMap<u16, Symbol>,
+}
+
+// Symbol files live next to the ROM, "roms/PONG" uses "roms/PONG.sym"
+pub fn default_symbol_path(rom_path: &str) -> String {
+    Path::new(rom_path)
+        .with_extension("sym")
+        .to_string_lossy()
+        .to_string()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(char::is_whitespace) && !name.contains(';')
+}
+
+impl SymbolTable {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut table = Self::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (entry, comment) = match line.find(';') {
+                Some(separator) => (&line[..separator], Some(line[separator + 1..].trim())),
+                None => (line, None),
+            };
+            let mut parts = entry.split_whitespace();
+            let address = parse_address(parts.next().unwrap_or(""))?;
+            let name = match (parts.next(), parts.next()) {
+                (Some(name), None) => name,
+                _ => return Err(format!("Invalid symbol in line {}: '{}'", index + 1, line)),
+            };
+            table.insert(address, name, comment)?;
+        }
+
+        Ok(table)
+    }
+
+    // Loads the file if it exists, an empty table that saves to it otherwise
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut table = match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text)?,
+            Err(_) if !Path::new(path).exists() => Self::default(),
+            Err(error) => return Err(format!("Error with reading '{}': {}", path, error)),
+        };
+        table.path = Some(path.to_string());
+        Ok(table)
+    }
+
+    pub fn save(&self) -> Result<String, String> {
+        let path = self
+            .path
+            .clone()
+            .ok_or_else(|| "No symbol file to save to".to_string())?;
+        fs::write(&path, self.to_text())
+            .map_err(|error| format!("Error with writing '{}': {}", path, error))?;
+        Ok(path)
+    }
+
+    pub fn to_text(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(address, symbol)| match &symbol.comment {
+                Some(comment) => format!("{:03X?} {} ; {}\n", address, symbol.name, comment),
+                None => format!("{:03X?} {}\n", address, symbol.name),
+            })
+            .collect()
+    }
+
+    pub fn insert(
+        &mut self,
+        address: u16,
+        name: &str,
+        comment: Option<&str>,
+    ) -> Result<(), String> {
+        if !is_valid_name(name) {
+            return Err(format!("Invalid symbol name '{}'", name));
+        }
+
+        self.symbols.insert(
+            address,
+            Symbol {
+                name: name.to_string(),
+                comment: comment
+                    .filter(|comment| !comment.is_empty())
+                    .map(|comment| comment.to_string()),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove(&mut self, address: u16) -> Option<Symbol> {
+        self.symbols.remove(&address)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u16, &Symbol)> {
+        self.symbols.iter()
+    }
+
+    pub fn get(&self, address: u16) -> Option<&Symbol> {
+        self.symbols.get(&address)
+    }
+
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.get(address).map(|symbol| symbol.name.as_str())
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.symbols
+            .iter()
+            .find(|(_, symbol)| symbol.name == name)
+            .map(|(&address, _)| address)
+    }
+
+    // The symbol name, or the address in hex when there is none
+    pub fn label(&self, address: u16) -> String {
+        match self.name(address) {
+            Some(name) => name.to_string(),
+            None => format!("{:03X?}", address),
+        }
+    }
+
+    // Names take precedence, so a label called "add" isn't read as 0xADD
+    pub fn resolve(&self, text: &str) -> Result<u16, String> {
+        match self.address_of(text) {
+            Some(address) => Ok(address),
+            None => parse_address(text),
+        }
+    }
+
+    // Mnemonic with the address operand replaced by its name, e.g. "CALL draw_paddle"
+    pub fn mnemonic(&self, instruction: &InstructionSet) -> String {
+        let mnemonic = instruction.mnemonic();
+        let address = match instruction.address_operand() {
+            Some(address) => address,
+            None => return mnemonic,
+        };
+
+        match (
+            self.name(address),
+            mnemonic.rfind(&format!("{:03X?}", address)),
+        ) {
+            (Some(name), Some(position)) => format!("{}{}", &mnemonic[..position], name),
+            _ => mnemonic,
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+
+use crate::chip8_vm::Chip8VM;
+use crate::instructions::decode_opcode;
+
+use super::SymbolTable;
+
+pub const TRACE_BUFFER_SIZE: usize = 1024;
+
+pub const V_REGISTER_NAMES: [&str; 16] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RegisterSnapshot {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack_pointer: u8,
+}
+
+impl RegisterSnapshot {
+    pub fn new(chip8_state: &Chip8VM) -> Self {
+        Self {
+            v: chip8_state.v,
+            i: chip8_state.i,
+            delay_timer: chip8_state.delay_timer,
+            sound_timer: chip8_state.sound_timer,
+            stack_pointer: chip8_state.stack_pointer,
+        }
+    }
+
+    pub fn changes(&self, after: &RegisterSnapshot) -> Vec<RegisterChange> {
+        let mut changes = Vec::new();
+        let mut compare = |name: &'static str, old: u16, new: u16| {
+            if old != new {
+                changes.push(RegisterChange { name, old, new });
+            }
+        };
+
+        for (index, name) in V_REGISTER_NAMES.iter().enumerate() {
+            compare(name, self.v[index] as u16, after.v[index] as u16);
+        }
+        compare("I", self.i, after.i);
+        compare("DT", self.delay_timer as u16, after.delay_timer as u16);
+        compare("ST", self.sound_timer as u16, after.sound_timer as u16);
+        compare("SP", self.stack_pointer as u16, after.stack_pointer as u16);
+
+        changes
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct RegisterChange {
+    pub name: &'static str,
+    pub old: u16,
+    pub new: u16,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub program_counter: u16,
+    pub opcode: u16,
+    pub mnemonic: String,
+    pub register_changes: Vec<RegisterChange>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>8} {:03X?}: {:04X?}  {:<16}",
+            self.cycle, self.program_counter, self.opcode, self.mnemonic
+        )?;
+        for (index, change) in self.register_changes.iter().enumerate() {
+            let separator = if index == 0 { "" } else { ", " };
+            write!(
+                f,
+                "{}{}: {:02X?} -> {:02X?}",
+                separator, change.name, change.old, change.new
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Tracer {
+    pub enabled: bool,
+    pub range: Option<RangeInclusive<u16>>,
+    entries: VecDeque<TraceEntry>,
+    output: Option<BufWriter<File>>,
+}
+
+impl Tracer {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            range: None,
+            entries: VecDeque::with_capacity(TRACE_BUFFER_SIZE),
+            output: None,
+        }
+    }
+
+    pub fn set_output_file(&mut self, path: &str) -> Result<(), String> {
+        let file = File::create(path)
+            .map_err(|error| format!("Error with creating '{}': {}", path, error))?;
+        self.output = Some(BufWriter::new(file));
+        self.enabled = true;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &VecDeque<TraceEntry> {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // Called after every executed instruction with the registers from before it ran
+    pub fn record(
+        &mut self,
+        program_counter: u16,
+        opcode: u16,
+        before: &RegisterSnapshot,
+        chip8_state: &Chip8VM,
+        symbols: &SymbolTable,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(range) = &self.range {
+            if !range.contains(&program_counter) {
+                return;
+            }
+        }
+
+        let entry = TraceEntry {
+            cycle: chip8_state.cycle_count,
+            program_counter,
+            opcode,
+            mnemonic: symbols.mnemonic(&decode_opcode(opcode)),
+            register_changes: before.changes(&RegisterSnapshot::new(chip8_state)),
+        };
+
+        if let Some(output) = &mut self.output {
+            if let Err(error) = writeln!(output, "{}", entry) {
+                println!("Error with writing trace file: {}", error);
+                self.output = None;
+            }
+        }
+
+        if self.entries.len() == TRACE_BUFFER_SIZE {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
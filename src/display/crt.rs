@@ -0,0 +1,252 @@
+use std::fmt;
+
+// Software post-processing of the display, done on the CPU so it doesn't
+// depend on what the graphics driver supports
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum CrtPreset {
+    #[default]
+    Off,
+    Scanlines,
+    // the grid of a handheld LCD
+    Lcd,
+    Crt,
+}
+
+pub const CRT_PRESETS: [CrtPreset; 4] = [
+    CrtPreset::Off,
+    CrtPreset::Scanlines,
+    CrtPreset::Lcd,
+    CrtPreset::Crt,
+];
+
+impl fmt::Display for CrtPreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CrtPreset::Off => "off",
+            CrtPreset::Scanlines => "scanlines",
+            CrtPreset::Lcd => "lcd",
+            CrtPreset::Crt => "crt",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl CrtPreset {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        CRT_PRESETS
+            .iter()
+            .copied()
+            .find(|preset| preset.to_string() == name)
+            .ok_or_else(|| format!("Unknown CRT preset '{}'", name))
+    }
+
+    // The preset after this one, the last one wraps around to off
+    pub fn next(self) -> Self {
+        let index = CRT_PRESETS
+            .iter()
+            .position(|&preset| preset == self)
+            .unwrap();
+        CRT_PRESETS[(index + 1) % CRT_PRESETS.len()]
+    }
+
+    pub fn effects(self) -> Option<CrtEffects> {
+        let effects = match self {
+            CrtPreset::Off => return None,
+            CrtPreset::Scanlines => CrtEffects {
+                scanlines: 0.5,
+                ..CrtEffects::default()
+            },
+            CrtPreset::Lcd => CrtEffects {
+                pixel_gap: 0.6,
+                ..CrtEffects::default()
+            },
+            CrtPreset::Crt => CrtEffects {
+                scanlines: 0.35,
+                pixel_gap: 0.2,
+                bloom: 0.5,
+                vignette: 0.35,
+            },
+        };
+        Some(effects)
+    }
+}
+
+// Strengths from 0 (no effect) to 1
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct CrtEffects {
+    // how much every other image row is darkened
+    pub scanlines: f32,
+    // how much the last row and column of every VM pixel is darkened
+    pub pixel_gap: f32,
+    // how much of the light around a pixel spills into its dark parts
+    pub bloom: f32,
+    // how much the corners are darkened
+    pub vignette: f32,
+}
+
+type Rgb = [f32; 3];
+
+// Number of pixels a box blur of the given radius averages at a position,
+// fewer at the edges
+fn window_size(position: usize, length: usize, radius: usize) -> f32 {
+    let start = position.saturating_sub(radius);
+    let end = (position + radius).min(length - 1);
+    (end - start + 1) as f32
+}
+
+fn add_to(sum: &mut Rgb, color: Rgb, sign: f32) {
+    (0..3).for_each(|channel| sum[channel] += color[channel] * sign);
+}
+
+// Box blur of the RGBA image scaled by scale, with a radius of one VM pixel,
+// handed out a row at a time. The edges average over the pixels that exist.
+// Both passes keep running sums of the window so the scale doesn't add work
+// per pixel, and the horizontal pass only runs once per VM row, as the
+// scaled rows of one are the same.
+struct Blur {
+    // horizontally blurred rows, one per VM row
+    rows: Vec<Rgb>,
+    // vertical sums of the window around the next row
+    sums: Vec<Rgb>,
+    // the row handed out last
+    row: Vec<Rgb>,
+    image_height: usize,
+    scale: usize,
+    y: usize,
+}
+
+impl Blur {
+    fn new(rgba: &[u8], width: usize, height: usize, scale: usize) -> Self {
+        let image_width = width * scale;
+        let mut rows = Vec::with_capacity(image_width * height);
+        for source_row in rgba.chunks_exact(width * 4) {
+            let row: Vec<Rgb> = source_row
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    let color = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+                    std::iter::repeat_n(color, scale)
+                })
+                .collect();
+            let mut sum = [0.0; 3];
+            row[..scale.min(image_width)]
+                .iter()
+                .for_each(|&color| add_to(&mut sum, color, 1.0));
+            for column in 0..image_width {
+                if column + scale < image_width {
+                    add_to(&mut sum, row[column + scale], 1.0);
+                }
+                if column > scale {
+                    add_to(&mut sum, row[column - scale - 1], -1.0);
+                }
+                let weight = 1.0 / window_size(column, image_width, scale);
+                rows.push([sum[0] * weight, sum[1] * weight, sum[2] * weight]);
+            }
+        }
+
+        let mut blur = Self {
+            rows,
+            sums: vec![[0.0; 3]; image_width],
+            row: vec![[0.0; 3]; image_width],
+            image_height: height * scale,
+            scale,
+            y: 0,
+        };
+        (0..scale.min(blur.image_height)).for_each(|y| blur.add_row(y, 1.0));
+        blur
+    }
+
+    fn add_row(&mut self, y: usize, sign: f32) {
+        let width = self.sums.len();
+        let row = &self.rows[(y / self.scale) * width..(y / self.scale + 1) * width];
+        self.sums
+            .iter_mut()
+            .zip(row)
+            .for_each(|(sum, &color)| add_to(sum, color, sign));
+    }
+
+    fn next_row(&mut self) -> &[Rgb] {
+        let (y, scale) = (self.y, self.scale);
+        if y + scale < self.image_height {
+            self.add_row(y + scale, 1.0);
+        }
+        if y > scale {
+            self.add_row(y - scale - 1, -1.0);
+        }
+        let weight = 1.0 / window_size(y, self.image_height, scale);
+        for (blurred, sum) in self.row.iter_mut().zip(&self.sums) {
+            *blurred = [sum[0] * weight, sum[1] * weight, sum[2] * weight];
+        }
+        self.y += 1;
+        &self.row
+    }
+}
+
+// Image pixels per VM pixel for the effects on a display drawn with the
+// given window pixels per VM pixel. Scanlines and gaps only come out even
+// when the image is drawn 1:1, a smooth scale gets the nearest whole one.
+pub fn crt_scale(display_scale: f32) -> usize {
+    (display_scale.round() as usize).max(1)
+}
+
+// Squared distance of a pixel from the centre of a line of pixels, 0 to 1
+fn centre_distance(position: usize, length: usize) -> f32 {
+    let distance = (position as f32 + 0.5) / (length as f32 / 2.0) - 1.0;
+    distance * distance
+}
+
+impl CrtEffects {
+    // Scales an RGBA image by scale and applies the effects to it
+    pub fn apply(&self, rgba: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+        let (image_width, image_height) = (width * scale, height * scale);
+        let mut glow = if self.bloom > 0.0 {
+            Some(Blur::new(rgba, width, height, scale))
+        } else {
+            None
+        };
+
+        // everything that only depends on the column, worked out once
+        let column_sources: Vec<usize> = (0..image_width).map(|x| (x / scale) * 4).collect();
+        let column_gaps: Vec<bool> = (0..image_width)
+            .map(|x| scale > 1 && x % scale == scale - 1)
+            .collect();
+        let column_distances: Vec<f32> = (0..image_width)
+            .map(|x| centre_distance(x, image_width))
+            .collect();
+
+        let mut image = vec![0xFF; image_width * image_height * 4];
+        for (y, row) in image.chunks_exact_mut(image_width * 4).enumerate() {
+            let source_row = &rgba[(y / scale) * width * 4..(y / scale + 1) * width * 4];
+            let glow_row = glow.as_mut().map(Blur::next_row);
+            let row_mask = if y % 2 == 1 {
+                1.0 - self.scanlines
+            } else {
+                1.0
+            };
+            // at scale 1 the gap would be all of the pixel
+            let row_gap = scale > 1 && y % scale == scale - 1;
+            let row_distance = centre_distance(y, image_height);
+
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let source = &source_row[column_sources[x]..column_sources[x] + 3];
+                let mask = if row_gap || column_gaps[x] {
+                    row_mask * (1.0 - self.pixel_gap)
+                } else {
+                    row_mask
+                };
+                // the distances add up to 2 in the corners
+                let vignette = 1.0 - self.vignette * (column_distances[x] + row_distance) / 2.0;
+
+                for channel in 0..3 {
+                    let masked = source[channel] as f32 * mask;
+                    // glow only brightens what the masks and the neighbours left darker
+                    let glow = glow_row.map_or(0.0, |glow_row| {
+                        (glow_row[x][channel] - masked).max(0.0) * self.bloom
+                    });
+                    // the cast saturates, which clamps to 0..=255
+                    pixel[channel] = ((masked + glow) * vignette + 0.5) as u8;
+                }
+            }
+        }
+        image
+    }
+}
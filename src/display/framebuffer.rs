@@ -0,0 +1,136 @@
+use super::Palette;
+use crate::chip8_vm::DISPLAY_SIZE;
+
+pub const FRAMEBUFFER_SIZE: usize = DISPLAY_SIZE[0] * DISPLAY_SIZE[1];
+
+// The palette index of a display pixel, the VM only has one bitplane so far
+pub fn pixel_planes(lit: bool) -> usize {
+    lit as usize
+}
+
+// The display as RGB pixels, for images and recordings at native resolution
+pub fn display_rgb(display_data: &[bool; FRAMEBUFFER_SIZE], palette: Palette) -> Vec<u8> {
+    display_data
+        .iter()
+        .flat_map(|&lit| palette.color(pixel_planes(lit)))
+        .collect()
+}
+
+// Brightness below which a fading pixel is treated as off, a colour channel
+// can't show anything dimmer
+const MIN_BRIGHTNESS: f32 = 1.0 / 255.0;
+
+// Games erase and redraw sprites with XOR, so a frame often catches a sprite
+// while it's erased. These modes only change how frames are shown, never the
+// VM's display_data.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum AntiFlicker {
+    #[default]
+    Off,
+    // average of this and the previous frame
+    Blend,
+    // pixels lit in this or the previous frame
+    Or,
+    // pixels fade out instead of turning off, losing this fraction of their
+    // brightness every frame
+    Persistence {
+        decay: f32,
+    },
+}
+
+impl AntiFlicker {
+    pub fn from_name(name: &str, decay: f32) -> Result<Self, String> {
+        match name {
+            "off" => Ok(AntiFlicker::Off),
+            "blend" => Ok(AntiFlicker::Blend),
+            "or" => Ok(AntiFlicker::Or),
+            "persistence" => Ok(AntiFlicker::Persistence { decay }),
+            _ => Err(format!("Unknown anti-flicker mode '{}'", name)),
+        }
+    }
+}
+
+// The VM display as RGBA pixels, rebuilt only when the shown frame or the
+// palette changed since the last frame
+pub struct Framebuffer {
+    rgba: Vec<u8>,
+    // display_data of the previous frame
+    display_data: [bool; FRAMEBUFFER_SIZE],
+    // how lit every pixel was shown, from 0 to 1
+    brightness: [f32; FRAMEBUFFER_SIZE],
+    palette: Palette,
+    dirty: bool,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        let mut framebuffer = Framebuffer {
+            rgba: vec![0; FRAMEBUFFER_SIZE * 4],
+            display_data: [false; FRAMEBUFFER_SIZE],
+            brightness: [0.0; FRAMEBUFFER_SIZE],
+            palette: Palette::default(),
+            dirty: true,
+        };
+        framebuffer.render();
+        framebuffer
+    }
+}
+
+impl Framebuffer {
+    pub fn width(&self) -> usize {
+        DISPLAY_SIZE[0]
+    }
+
+    pub fn height(&self) -> usize {
+        DISPLAY_SIZE[1]
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    // Shows the next frame, returns whether the pixels changed and have to be
+    // uploaded again
+    pub fn update(
+        &mut self,
+        display_data: &[bool; FRAMEBUFFER_SIZE],
+        palette: Palette,
+        anti_flicker: AntiFlicker,
+    ) -> bool {
+        let mut brightness = [0.0; FRAMEBUFFER_SIZE];
+        for (index, value) in brightness.iter_mut().enumerate() {
+            let (lit, was_lit) = (display_data[index], self.display_data[index]);
+            *value = match anti_flicker {
+                AntiFlicker::Off => lit as u8 as f32,
+                AntiFlicker::Blend => (lit as u8 + was_lit as u8) as f32 / 2.0,
+                AntiFlicker::Or => (lit || was_lit) as u8 as f32,
+                AntiFlicker::Persistence { decay } => {
+                    let faded = self.brightness[index] * (1.0 - decay);
+                    if lit {
+                        1.0
+                    } else if faded < MIN_BRIGHTNESS {
+                        0.0
+                    } else {
+                        faded
+                    }
+                }
+            };
+        }
+        self.display_data = *display_data;
+
+        if brightness != self.brightness || palette != self.palette {
+            self.brightness = brightness;
+            self.palette = palette;
+            self.render();
+        }
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    fn render(&mut self) {
+        for (pixel, &brightness) in self.rgba.chunks_exact_mut(4).zip(self.brightness.iter()) {
+            let [red, green, blue] = self.palette.fade(pixel_planes(true), brightness);
+            pixel.copy_from_slice(&[red, green, blue, 0xFF]);
+        }
+        self.dirty = true;
+    }
+}
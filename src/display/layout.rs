@@ -0,0 +1,151 @@
+use crate::chip8_vm::DISPLAY_SIZE;
+
+// Window pixels per VM pixel unless --scale says otherwise
+pub const DEFAULT_SCALE: usize = 16;
+// Smallest scale the window can be resized to
+const MIN_SCALE: usize = 4;
+
+const DEBUG_PANEL_WIDTH: f32 = 300.0;
+const DEBUG_PANEL_HEIGHT: f32 = 300.0;
+// Room the debugger panels take right of and below the game display
+pub const DEBUG_PANELS_SIZE: (f32, f32) = (DEBUG_PANEL_WIDTH * 2.0, DEBUG_PANEL_HEIGHT);
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Scaling {
+    // the largest whole number of window pixels per VM pixel that fits
+    #[default]
+    Integer,
+    // fill the window, keeping the aspect ratio
+    Smooth,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Area {
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+}
+
+impl Area {
+    fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Area {
+            position: [x, y],
+            size: [width.max(0.0), height.max(0.0)],
+        }
+    }
+}
+
+pub fn window_size(scale: usize, debug_mode: bool) -> (f32, f32) {
+    let (width, height) = (
+        (DISPLAY_SIZE[0] * scale) as f32,
+        (DISPLAY_SIZE[1] * scale) as f32,
+    );
+
+    if debug_mode {
+        (width + DEBUG_PANELS_SIZE.0, height + DEBUG_PANELS_SIZE.1)
+    } else {
+        (width, height)
+    }
+}
+
+// The debugger needs the bottom panels wider than the game display at the
+// smallest scale
+pub fn min_window_size(debug_mode: bool) -> (f32, f32) {
+    let (width, height) = window_size(MIN_SCALE, debug_mode);
+    if debug_mode {
+        (width.max(DEBUG_PANEL_WIDTH * 4.0), height)
+    } else {
+        (width, height)
+    }
+}
+
+// Where the game display and the debugger panels go in a window of any size
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Layout {
+    pub display: Area,
+    pub registers: Area,
+    pub disassembly: Area,
+    pub memory_table: Area,
+    pub trace: Area,
+    pub call_stack: Area,
+}
+
+impl Layout {
+    pub fn new((width, height): (f32, f32), debug_mode: bool, scaling: Scaling) -> Self {
+        let mut layout = Layout::default();
+
+        // the panels keep their size, the game display gets the rest
+        let (game_width, game_height) = if debug_mode {
+            (
+                (width - DEBUG_PANELS_SIZE.0).max(0.0),
+                (height - DEBUG_PANELS_SIZE.1).max(0.0),
+            )
+        } else {
+            (width, height)
+        };
+
+        let mut scale =
+            (game_width / DISPLAY_SIZE[0] as f32).min(game_height / DISPLAY_SIZE[1] as f32);
+        if scaling == Scaling::Integer {
+            scale = scale.floor().max(1.0);
+        }
+        let (display_width, display_height) = (
+            DISPLAY_SIZE[0] as f32 * scale,
+            DISPLAY_SIZE[1] as f32 * scale,
+        );
+        layout.display = Area::new(
+            ((game_width - display_width) / 2.0).floor(),
+            ((game_height - display_height) / 2.0).floor(),
+            display_width,
+            display_height,
+        );
+
+        if debug_mode {
+            layout.registers = Area::new(game_width, 0.0, DEBUG_PANEL_WIDTH, game_height);
+            layout.disassembly = Area::new(
+                game_width + DEBUG_PANEL_WIDTH,
+                0.0,
+                DEBUG_PANEL_WIDTH,
+                game_height,
+            );
+            layout.memory_table = Area::new(
+                0.0,
+                game_height,
+                width - DEBUG_PANEL_WIDTH * 3.0,
+                DEBUG_PANEL_HEIGHT,
+            );
+            layout.trace = Area::new(
+                width - DEBUG_PANEL_WIDTH * 3.0,
+                game_height,
+                DEBUG_PANEL_WIDTH * 2.0,
+                DEBUG_PANEL_HEIGHT,
+            );
+            layout.call_stack = Area::new(
+                width - DEBUG_PANEL_WIDTH,
+                game_height,
+                DEBUG_PANEL_WIDTH,
+                DEBUG_PANEL_HEIGHT,
+            );
+        }
+        layout
+    }
+
+    // Window pixels per VM pixel
+    pub fn scale(&self) -> f32 {
+        self.display.size[0] / DISPLAY_SIZE[0] as f32
+    }
+
+    // The VM pixel under a window position
+    pub fn pixel_at(&self, [x, y]: [f32; 2]) -> Option<(usize, usize)> {
+        let scale = self.scale();
+        let (x, y) = (
+            (x - self.display.position[0]) / scale,
+            (y - self.display.position[1]) / scale,
+        );
+        if x >= 0.0 && y >= 0.0 && (x as usize) < DISPLAY_SIZE[0] && (y as usize) < DISPLAY_SIZE[1]
+        {
+            Some((x as usize, y as usize))
+        } else {
+            None
+        }
+    }
+}
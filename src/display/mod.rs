@@ -0,0 +1,43 @@
+mod crt;
+mod framebuffer;
+mod layout;
+mod palette;
+mod recorder;
+mod screenshot;
+
+pub use crt::{crt_scale, CrtEffects, CrtPreset};
+pub use framebuffer::{AntiFlicker, Framebuffer};
+pub use layout::{
+    min_window_size, window_size, Area, Layout, Scaling, DEBUG_PANELS_SIZE, DEFAULT_SCALE,
+};
+pub use palette::{Palette, Rgb, Theme};
+pub use recorder::{GifSink, RawSink, Recorder};
+pub use screenshot::{check_screenshot_scale, save_screenshot};
+
+// How the emulator window draws the VM display
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DisplaySettings {
+    pub palette: Palette,
+    pub anti_flicker: AntiFlicker,
+    pub crt: CrtPreset,
+    // window pixels per VM pixel the window opens with
+    pub scale: usize,
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+    // image pixels per VM pixel in screenshots
+    pub screenshot_scale: usize,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            palette: Palette::default(),
+            anti_flicker: AntiFlicker::default(),
+            crt: CrtPreset::default(),
+            scale: DEFAULT_SCALE,
+            scaling: Scaling::default(),
+            fullscreen: false,
+            screenshot_scale: 1,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+
+pub type Rgb = [u8; 3];
+
+// Colours indexed by the bitplanes a pixel is set in: background, plane 1,
+// plane 2 and both. XO-CHIP ROMs draw on two planes, everything else only
+// ever uses the first two colours.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Theme::Classic.palette()
+    }
+}
+
+// "#FFB000" or "FFB000"
+fn parse_color(text: &str) -> Result<Rgb, String> {
+    let digits = text.trim().trim_start_matches('#');
+    let value = match u32::from_str_radix(digits, 16) {
+        Ok(value) if digits.len() == 6 => value,
+        _ => return Err(format!("Invalid colour '{}', expected RRGGBB", text)),
+    };
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+impl Palette {
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    pub fn set_background(&mut self, text: &str) -> Result<(), String> {
+        self.colors[0] = parse_color(text)?;
+        Ok(())
+    }
+
+    // The other plane colours follow the foreground unless set on their own
+    pub fn set_foreground(&mut self, text: &str) -> Result<(), String> {
+        let color = parse_color(text)?;
+        self.colors[1..].iter_mut().for_each(|plane| *plane = color);
+        Ok(())
+    }
+
+    // All four colours, comma separated: "000000,FFFFFF,FF0000,00FF00"
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let colors: Vec<Rgb> = text.split(',').map(parse_color).collect::<Result<_, _>>()?;
+        if colors.len() != 4 {
+            return Err(format!(
+                "Invalid palette '{}', expected 4 comma separated colours",
+                text
+            ));
+        }
+        Ok(Self {
+            colors: [colors[0], colors[1], colors[2], colors[3]],
+        })
+    }
+
+    pub fn color(&self, planes: usize) -> Rgb {
+        self.colors[planes & 0b11]
+    }
+
+    // The colour of the planes mixed into the background, for pixels that
+    // are only partly lit by an anti-flicker mode
+    pub fn fade(&self, planes: usize, brightness: f32) -> Rgb {
+        let (background, color) = (self.background(), self.color(planes));
+        let mut faded = background;
+        for channel in 0..3 {
+            let (from, to) = (background[channel] as f32, color[channel] as f32);
+            faded[channel] = (from + (to - from) * brightness).round() as u8;
+        }
+        faded
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Theme {
+    Classic,
+    GreenPhosphor,
+    Amber,
+    LcdGrey,
+    HighContrast,
+}
+
+pub const THEMES: [Theme; 5] = [
+    Theme::Classic,
+    Theme::GreenPhosphor,
+    Theme::Amber,
+    Theme::LcdGrey,
+    Theme::HighContrast,
+];
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Theme::Classic => "classic",
+            Theme::GreenPhosphor => "green",
+            Theme::Amber => "amber",
+            Theme::LcdGrey => "lcd",
+            Theme::HighContrast => "high-contrast",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl Theme {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        THEMES
+            .iter()
+            .copied()
+            .find(|theme| theme.to_string() == name)
+            .ok_or_else(|| format!("Unknown theme '{}'", name))
+    }
+
+    pub fn palette(self) -> Palette {
+        let colors = match self {
+            Theme::Classic => [
+                [0x00, 0x00, 0x00],
+                [0xFF, 0xFF, 0xFF],
+                [0xAA, 0xAA, 0xAA],
+                [0x55, 0x55, 0x55],
+            ],
+            Theme::GreenPhosphor => [
+                [0x0A, 0x14, 0x0A],
+                [0x33, 0xFF, 0x66],
+                [0x1A, 0x99, 0x3D],
+                [0xB3, 0xFF, 0xC6],
+            ],
+            Theme::Amber => [
+                [0x14, 0x0C, 0x00],
+                [0xFF, 0xB0, 0x00],
+                [0x99, 0x66, 0x00],
+                [0xFF, 0xDD, 0x88],
+            ],
+            // the Game Boy's greenish grey, dark pixels on a light screen
+            Theme::LcdGrey => [
+                [0xC4, 0xCF, 0xA1],
+                [0x41, 0x41, 0x41],
+                [0x8B, 0x95, 0x6D],
+                [0x6B, 0x73, 0x53],
+            ],
+            Theme::HighContrast => [
+                [0x00, 0x00, 0x00],
+                [0xFF, 0xFF, 0x00],
+                [0x00, 0xFF, 0xFF],
+                [0xFF, 0x00, 0xFF],
+            ],
+        };
+        Palette { colors }
+    }
+}
@@ -0,0 +1,182 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use gif::SetParameter;
+
+use super::framebuffer::{display_rgb, pixel_planes, FRAMEBUFFER_SIZE};
+use super::Palette;
+use crate::chip8_vm::{CYCLES_PER_SECOND, DISPLAY_SIZE};
+
+// Recordings run at a fixed frame rate of VM time, not of the window, so a
+// frame lasts 2/100 s which GIF frame delays can hold exactly
+pub const RECORDING_FPS: u32 = 50;
+const CYCLES_PER_FRAME: u32 = CYCLES_PER_SECOND / RECORDING_FPS;
+
+pub type Frame = [bool; FRAMEBUFFER_SIZE];
+
+// Somewhere to send display frames at RECORDING_FPS
+pub trait FrameSink {
+    fn write(&mut self, frame: &Frame);
+
+    // Called once when the recording stops, returns the path written to
+    fn finish(&mut self) -> Result<String, String>;
+}
+
+// Native resolution animated GIF, frames that don't change are merged into
+// one longer frame
+pub struct GifSink {
+    path: String,
+    // taken when the file is finalized
+    encoder: Option<gif::Encoder<BufWriter<File>>>,
+    // the last frame and for how many frames it has been shown
+    pending: Option<(Frame, u16)>,
+}
+
+impl GifSink {
+    pub fn create(path: &str, palette: Palette) -> Result<Self, String> {
+        let error = |error| format!("Error with creating '{}': {}", path, error);
+
+        let file = File::create(path).map_err(error)?;
+        let colors: Vec<u8> = palette.colors.iter().flatten().copied().collect();
+        let mut encoder = gif::Encoder::new(
+            BufWriter::new(file),
+            DISPLAY_SIZE[0] as u16,
+            DISPLAY_SIZE[1] as u16,
+            &colors,
+        )
+        .map_err(error)?;
+        encoder.set(gif::Repeat::Infinite).map_err(error)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            encoder: Some(encoder),
+            pending: None,
+        })
+    }
+
+    fn write_pending(&mut self) {
+        let (encoder, (frame, count)) = match (&mut self.encoder, self.pending.take()) {
+            (Some(encoder), Some(pending)) => (encoder, pending),
+            _ => return,
+        };
+        let pixels: Vec<u8> = frame.iter().map(|&lit| pixel_planes(lit) as u8).collect();
+        let mut gif_frame = gif::Frame::from_indexed_pixels(
+            DISPLAY_SIZE[0] as u16,
+            DISPLAY_SIZE[1] as u16,
+            &pixels,
+            None,
+        );
+        gif_frame.delay = count * (100 / RECORDING_FPS) as u16;
+        if let Err(error) = encoder.write_frame(&gif_frame) {
+            println!("Error with writing '{}': {}", self.path, error);
+            self.encoder = None;
+        }
+    }
+}
+
+impl FrameSink for GifSink {
+    fn write(&mut self, frame: &Frame) {
+        match &mut self.pending {
+            // a GIF delay is 16 bits of 1/100 s
+            Some((pending, count)) if pending == frame && *count < u16::MAX / 2 => *count += 1,
+            _ => {
+                self.write_pending();
+                self.pending = Some((*frame, 1));
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        self.write_pending();
+        // dropping the encoder writes the GIF trailer
+        match self.encoder.take() {
+            Some(_) => Ok(self.path.clone()),
+            None => Err(format!("Error with writing '{}'", self.path)),
+        }
+    }
+}
+
+// Every frame as raw 24-bit RGB, for video encoders:
+// ffmpeg -f rawvideo -pixel_format rgb24 -video_size 64x32 -framerate 50 -i FILE
+pub struct RawSink {
+    path: String,
+    palette: Palette,
+    writer: Option<BufWriter<File>>,
+}
+
+impl RawSink {
+    pub fn create(path: &str, palette: Palette) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|error| format!("Error with creating '{}': {}", path, error))?;
+        Ok(Self {
+            path: path.to_string(),
+            palette,
+            writer: Some(BufWriter::new(file)),
+        })
+    }
+}
+
+impl FrameSink for RawSink {
+    fn write(&mut self, frame: &Frame) {
+        let pixels = display_rgb(frame, self.palette);
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        if let Err(error) = writer.write_all(&pixels) {
+            println!("Error with writing '{}': {}", self.path, error);
+            self.writer = None;
+        }
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        match self.writer.take() {
+            Some(mut writer) => writer
+                .flush()
+                .map(|()| self.path.clone())
+                .map_err(|error| format!("Error with writing '{}': {}", self.path, error)),
+            None => Err(format!("Error with writing '{}'", self.path)),
+        }
+    }
+}
+
+// Samples the display into its sinks as the VM runs
+#[derive(Default)]
+pub struct Recorder {
+    sinks: Vec<Box<dyn FrameSink>>,
+    // cycles since the last frame
+    cycles: u32,
+}
+
+impl Recorder {
+    pub fn add_sink(&mut self, sink: Box<dyn FrameSink>) {
+        if self.sinks.is_empty() {
+            self.cycles = 0;
+        }
+        self.sinks.push(sink);
+    }
+
+    pub fn is_recording(&self) -> bool {
+        !self.sinks.is_empty()
+    }
+
+    // Called once per VM cycle, the first cycle of a recording is its first frame
+    pub fn advance(&mut self, display_data: &Frame) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        if self.cycles == 0 {
+            self.sinks
+                .iter_mut()
+                .for_each(|sink| sink.write(display_data));
+        }
+        self.cycles = (self.cycles + 1) % CYCLES_PER_FRAME;
+    }
+
+    // Stops recording, returns the paths of the finished files
+    pub fn finish(&mut self) -> Result<Vec<String>, String> {
+        // finish every sink even if an earlier one failed
+        let results: Vec<_> = self.sinks.drain(..).map(|mut sink| sink.finish()).collect();
+        results.into_iter().collect()
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+
+use super::framebuffer::{display_rgb, FRAMEBUFFER_SIZE};
+use super::Palette;
+use crate::chip8_vm::DISPLAY_SIZE;
+
+// Already a 4096x2048 image, larger ones would take hundreds of megabytes
+const MAX_SCREENSHOT_SCALE: usize = 64;
+
+pub fn check_screenshot_scale(scale: usize) -> Result<usize, String> {
+    if scale == 0 || scale > MAX_SCREENSHOT_SCALE {
+        return Err(format!(
+            "Invalid scale '{}', expected 1 to {}",
+            scale, MAX_SCREENSHOT_SCALE
+        ));
+    }
+    Ok(scale)
+}
+
+// The display as RGB rows, every VM pixel drawn as a scale x scale square
+fn screenshot_pixels(
+    display_data: &[bool; FRAMEBUFFER_SIZE],
+    palette: Palette,
+    scale: usize,
+) -> Vec<u8> {
+    let width = DISPLAY_SIZE[0] * scale;
+    let mut pixels = Vec::with_capacity(FRAMEBUFFER_SIZE * scale * scale * 3);
+    for row in display_rgb(display_data, palette).chunks_exact(DISPLAY_SIZE[0] * 3) {
+        let mut line = Vec::with_capacity(width * 3);
+        for color in row.chunks_exact(3) {
+            (0..scale).for_each(|_| line.extend_from_slice(color));
+        }
+        (0..scale).for_each(|_| pixels.extend_from_slice(&line));
+    }
+    pixels
+}
+
+pub fn save_screenshot(
+    path: &str,
+    display_data: &[bool; FRAMEBUFFER_SIZE],
+    palette: Palette,
+    scale: usize,
+) -> Result<(), String> {
+    check_screenshot_scale(scale)?;
+    let error = |error: &dyn fmt::Display| format!("Error with writing '{}': {}", path, error);
+
+    let file = File::create(path).map_err(|e| error(&e))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        (DISPLAY_SIZE[0] * scale) as u32,
+        (DISPLAY_SIZE[1] * scale) as u32,
+    );
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+    writer
+        .write_image_data(&screenshot_pixels(display_data, palette, scale))
+        .map_err(|e| error(&e))
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 pub enum InstructionSet {
     MachineLanguageSubroutine(u16),
     ClearScreen,
@@ -37,9 +39,9 @@ pub enum InstructionSet {
     None, // temp
 }
 
-impl ToString for InstructionSet {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
             InstructionSet::ClearScreen => {
                 "Clearing the Screen".to_string()
             }
@@ -231,6 +233,90 @@ impl ToString for InstructionSet {
                      x
                 )
             }
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl InstructionSet {
+    pub fn mnemonic(&self) -> String {
+        match self {
+            InstructionSet::MachineLanguageSubroutine(opcode) => {
+                format!("SYS {:03X?}", opcode & 0x0FFF)
+            }
+            InstructionSet::ClearScreen => "CLS".to_string(),
+            InstructionSet::ReturnFromSubroutine => "RET".to_string(),
+            InstructionSet::ExecuteSubroutine(address) => format!("CALL {:03X?}", address),
+            InstructionSet::JumpToAddress(address) => format!("JP {:03X?}", address),
+            InstructionSet::SkipFollowingIfRegisterIsEqualToValue(x, value) => {
+                format!("SE V{:X?}, {:02X?}", x, value)
+            }
+            InstructionSet::SkipFollowingIfRegisterIsNotEqualToValue(x, value) => {
+                format!("SNE V{:X?}, {:02X?}", x, value)
+            }
+            InstructionSet::SkipFollowingIfVxIsEqualToVy(x, y) => {
+                format!("SE V{:X?}, V{:X?}", x, y)
+            }
+            InstructionSet::SkipFollowingIfVxrIsNotEqualToVy(x, y) => {
+                format!("SNE V{:X?}, V{:X?}", x, y)
+            }
+            InstructionSet::SkipFollowingIfKeyCorrespondingToVxIsPressed(x) => {
+                format!("SKP V{:X?}", x)
+            }
+            InstructionSet::SkipFollowingIfKeyCorrespondingToVxIsNotPressed(x) => {
+                format!("SKNP V{:X?}", x)
+            }
+            InstructionSet::StoreInRegister(x, value) => format!("LD V{:X?}, {:02X?}", x, value),
+            InstructionSet::AddToRegister(x, value) => format!("ADD V{:X?}, {:02X?}", x, value),
+            InstructionSet::CopyVyValueToVx(x, y) => format!("LD V{:X?}, V{:X?}", x, y),
+            InstructionSet::SetVxToVxOrVy(x, y) => format!("OR V{:X?}, V{:X?}", x, y),
+            InstructionSet::SetVxToVxAndVy(x, y) => format!("AND V{:X?}, V{:X?}", x, y),
+            InstructionSet::SetVxToVxXorVy(x, y) => format!("XOR V{:X?}, V{:X?}", x, y),
+            InstructionSet::AddVyValueToVx(x, y) => format!("ADD V{:X?}, V{:X?}", x, y),
+            InstructionSet::SubtractVyValueFromVx(x, y) => format!("SUB V{:X?}, V{:X?}", x, y),
+            InstructionSet::StoreVyValueShiftedRightOneBitInVx(x, y) => {
+                format!("SHR V{:X?}, V{:X?}", x, y)
+            }
+            InstructionSet::SetVxToValueOfVyMinusVx(x, y) => format!("SUBN V{:X?}, V{:X?}", x, y),
+            InstructionSet::StoreVyValueShiftedLeftOneBitInVx(x, y) => {
+                format!("SHL V{:X?}, V{:X?}", x, y)
+            }
+            InstructionSet::StoreAddressInRegisterI(address) => format!("LD I, {:03X?}", address),
+            InstructionSet::JumpToAddressWithV0Offset(address) => {
+                format!("JP V0, {:03X?}", address)
+            }
+            InstructionSet::SetVxToRandomNumberWithAMaskOf(x, mask) => {
+                format!("RND V{:X?}, {:02X?}", x, mask)
+            }
+            InstructionSet::DrawSprite(x, y, n) => format!("DRW V{:X?}, V{:X?}, {:X?}", x, y, n),
+            InstructionSet::StoreDelayTimerInRegisterVx(x) => format!("LD V{:X?}, DT", x),
+            InstructionSet::WaitForAKeyPress(x) => format!("LD V{:X?}, K", x),
+            InstructionSet::SetDelayTimerToVx(x) => format!("LD DT, V{:X?}", x),
+            InstructionSet::SetSoundTimerToVx(x) => format!("LD ST, V{:X?}", x),
+            InstructionSet::AddVxToRegisterI(x) => format!("ADD I, V{:X?}", x),
+            InstructionSet::SetIToTheMemoryAddressOfSpriteCorrespondingToVx(x) => {
+                format!("LD F, V{:X?}", x)
+            }
+            InstructionSet::StoreTheBinaryCodedDecimalEquivalentOfVx(x) => {
+                format!("LD B, V{:X?}", x)
+            }
+            InstructionSet::StoreValuesOfV0ToVxInclusiveInMemoryStartingAtAddressI(x) => {
+                format!("LD [I], V{:X?}", x)
+            }
+            InstructionSet::FillRegistersV0ToVxInclusiveWithMemoryStartingAtAddressI(x) => {
+                format!("LD V{:X?}, [I]", x)
+            }
+            InstructionSet::None => "???".to_string(),
+        }
+    }
+    // The memory address an instruction refers to, for showing labels instead
+    pub fn address_operand(&self) -> Option<u16> {
+        match *self {
+            InstructionSet::ExecuteSubroutine(address)
+            | InstructionSet::JumpToAddress(address)
+            | InstructionSet::StoreAddressInRegisterI(address)
+            | InstructionSet::JumpToAddressWithV0Offset(address) => Some(address),
+            _ => None,
         }
     }
 }
@@ -1,50 +1,181 @@
 use ggez::conf;
 use ggez::event::{self};
 
+mod audio;
 mod chip8_vm;
 mod debug;
+mod display;
 mod fonts_sprites;
 mod instructions;
 mod main_state;
 mod setup;
 mod test;
+mod tui;
 mod utils;
 mod write_to_memory;
 
+use audio::{Beeper, NullSink, RodioSink, WavSink};
 use chip8_vm::*;
+use debug::{
+    default_symbol_path, DapServer, Debugger, GdbStub, Instruments, Profiler, RemoteDebuggers,
+    Repl, SymbolTable, Tracer,
+};
+use display::{window_size, GifSink, RawSink, Recorder};
 use main_state::*;
 use setup::*;
+use tui::Tui;
 use utils::read_file_as_bytes;
 
+// Settings the emulator can't start without, a bad one ends it with the error
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|error| {
+        println!("Error: {}", error);
+        std::process::exit(1);
+    })
+}
+
 fn main() -> ggez::GameResult {
     let matches = setup_cmd_program_arguments();
 
-    let rom_path = get_rom_path(matches).unwrap();
+    let rom_path = get_rom_path(&matches).unwrap();
     println!("ROM file path you provided '{}'", rom_path);
 
     let rom_data = read_file_as_bytes(rom_path.as_str()).unwrap();
 
+    let debug_mode = get_debug_mode(&matches);
+
+    let mut tracer = Tracer::new(debug_mode);
+    tracer.range = or_exit(get_trace_range(&matches));
+    if let Some(trace_path) = get_trace_path(&matches) {
+        or_exit(tracer.set_output_file(trace_path.as_str()));
+    }
+
+    let symbol_path = get_symbol_path(&matches).unwrap_or_else(|| default_symbol_path(&rom_path));
+    // the symbol file is read by default, so a broken one mustn't stop the ROM from starting
+    let symbols = SymbolTable::load(symbol_path.as_str()).unwrap_or_else(|error| {
+        println!("Ignoring symbol file '{}': {}", symbol_path, error);
+        SymbolTable::default()
+    });
+
+    let on_unknown_opcode = get_unknown_opcode_action(&matches);
+
+    let mut beeper = Beeper::new(or_exit(get_beeper_settings(&matches)));
+    if let Some(audio_out_path) = get_audio_out_path(&matches) {
+        let wav_sink = or_exit(WavSink::create(audio_out_path.as_str()));
+        println!("Recording audio to {}", wav_sink.path());
+        beeper.add_sink(Box::new(wav_sink));
+    }
+
+    let display_settings = or_exit(get_display_settings(&matches));
+    let mut recorder = Recorder::default();
+    if let Some(gif_path) = get_record_gif_path(&matches) {
+        let gif_sink = or_exit(GifSink::create(gif_path.as_str(), display_settings.palette));
+        println!("Recording to {}", gif_path);
+        recorder.add_sink(Box::new(gif_sink));
+    }
+    if let Some(frames_path) = get_record_frames_path(&matches) {
+        let raw_sink = or_exit(RawSink::create(
+            frames_path.as_str(),
+            display_settings.palette,
+        ));
+        println!("Recording to {}", frames_path);
+        recorder.add_sink(Box::new(raw_sink));
+    }
+
+    if get_repl_mode(&matches) {
+        let mut debugger = Debugger::new(true);
+        debugger.symbols = symbols;
+        debugger.on_unknown_opcode = on_unknown_opcode;
+        let mut repl = Repl::new(Chip8VM::new(&rom_data), debugger, tracer);
+        repl.beeper = beeper;
+        repl.recorder = recorder;
+        repl.palette = display_settings.palette;
+        if let Err(error) = repl.run() {
+            println!("Error: {}", error);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut profiler = Profiler::new(debug_mode);
+    if let Some(profile_path) = get_profile_path(&matches) {
+        profiler.set_report_file(profile_path.as_str());
+    }
+    if let Some(coverage_path) = get_coverage_path(&matches) {
+        profiler.set_coverage_file(coverage_path.as_str());
+    }
+
+    let gdb_stub = get_gdb_address(&matches).map(|address| {
+        let gdb_stub =
+            or_exit(GdbStub::bind(address.as_str()).map_err(|error| {
+                format!("Error with starting GDB stub on '{}': {}", address, error)
+            }));
+        println!(
+            "Waiting for GDB client on {}",
+            gdb_stub.local_address().unwrap_or(address)
+        );
+        gdb_stub
+    });
+
+    let dap_server = get_dap_address(&matches).map(|address| {
+        let dap_server = or_exit(DapServer::bind(address.as_str()).map_err(|error| {
+            format!("Error with starting DAP server on '{}': {}", address, error)
+        }));
+        println!(
+            "Waiting for DAP client on {}",
+            dap_server.local_address().unwrap_or(address)
+        );
+        dap_server
+    });
+
+    let remote_debuggers = RemoteDebuggers {
+        gdb_stub,
+        dap_server,
+    };
+    let mut debugger = Debugger::new(debug_mode || remote_debuggers.is_active());
+    debugger.symbols = symbols;
+    debugger.on_unknown_opcode = on_unknown_opcode;
+
+    if !get_sound_enabled(&matches) {
+        beeper.add_sink(Box::new(NullSink));
+    } else if let Some(sink) = RodioSink::new() {
+        beeper.add_sink(Box::new(sink));
+    } else {
+        println!("No audio device found, playing without sound");
+        beeper.add_sink(Box::new(NullSink));
+    }
+
+    if get_tui_mode(&matches) {
+        let mut tui = Tui::new(Chip8VM::new(&rom_data), debugger);
+        tui.beeper = beeper;
+        tui.recorder = recorder;
+        if let Err(error) = tui.run() {
+            println!("Error: {}", error);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let size = window_size(display_settings.scale, debug_mode);
     let cb = ggez::ContextBuilder::new("CHIP-8 VM", "ggez")
         .window_setup(conf::WindowSetup::default().title("CHIP-8 VM"))
-        .window_mode(conf::WindowMode::default().resizable(false).dimensions(
-            (DISPLAY_SIZE[0] * SCALE) as f32
-                + if cfg!(debug_assertions) {
-                    DEBUG_EXTRA_DISPLAY_SIZE[0]
-                } else {
-                    0.0
-                },
-            (DISPLAY_SIZE[1] * SCALE) as f32
-                + if cfg!(debug_assertions) {
-                    DEBUG_EXTRA_DISPLAY_SIZE[1]
-                } else {
-                    0.0
-                },
-        ));
+        .window_mode(window_mode(size, debug_mode, display_settings.fullscreen));
     let (ref mut ctx, event_loop) = &mut cb.build()?;
 
     let hidpi_factor = event_loop.get_primary_monitor().get_hidpi_factor() as f32;
 
-    let state = &mut MainState::new(ctx, hidpi_factor, &rom_data)?;
+    let state = &mut MainState::new(
+        ctx,
+        hidpi_factor,
+        &rom_data,
+        display_settings,
+        beeper,
+        recorder,
+        debugger,
+        Instruments::new(tracer, profiler),
+        remote_debuggers,
+        debug_mode,
+    )?;
 
     event::run(ctx, event_loop, state)
 }
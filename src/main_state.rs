@@ -1,72 +1,204 @@
-use crate::{chip8_vm::*, debug::ImGuiWrapper, instructions::decode_opcode};
+use crate::{
+    audio::Beeper,
+    chip8_vm::*,
+    debug::{run_cycle, save_coverage, Debugger, ImGuiWrapper, Instruments, RemoteDebuggers},
+    display::{
+        crt_scale, min_window_size, save_screenshot, window_size, CrtPreset, DisplaySettings,
+        Framebuffer, GifSink, Layout, Recorder, Rgb, Scaling, DEBUG_PANELS_SIZE,
+    },
+    utils::next_free_path,
+};
 use ggez::{
+    conf::{FullscreenType, WindowMode},
     event::{EventHandler, KeyCode, KeyMods, MouseButton},
-    graphics, Context, GameResult,
+    graphics, Context, GameError, GameResult,
 };
 
-fn fetch_opcode(memory: &[u8], pc: u16) -> Result<u16, ()> {
-    if pc >= MEMORY_SIZE as u16 {
-        return Err(());
-    }
+// Resizable window of the given size, with room for the debugger panels when
+// the overlay is open
+pub fn window_mode((width, height): (f32, f32), debug_mode: bool, fullscreen: bool) -> WindowMode {
+    let (min_width, min_height) = min_window_size(debug_mode);
+    WindowMode::default()
+        .dimensions(width.max(min_width), height.max(min_height))
+        .min_dimensions(min_width, min_height)
+        .resizable(true)
+        .fullscreen_type(if fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Windowed
+        })
+}
 
-    Ok((memory[pc as usize] as u16) << 8 | memory[pc as usize + 1] as u16)
+fn to_color([red, green, blue]: Rgb) -> graphics::Color {
+    graphics::Color::from_rgb(red, green, blue)
 }
 
 pub struct MainState {
     imgui_wrapper: ImGuiWrapper,
     hidpi_factor: f32,
     chip8_state: Chip8VM,
-    debug_run_next: bool,
+    display_settings: DisplaySettings,
+    framebuffer: Framebuffer,
+    // the framebuffer as last uploaded to the GPU, with the CRT preset
+    // applied to it and the scale it was applied at
+    display_image: Option<(graphics::Image, CrtPreset, usize)>,
+    // size of the window when it isn't fullscreen
+    window_size: (f32, f32),
+    beeper: Beeper,
+    recorder: Recorder,
+    debugger: Debugger,
+    instruments: Instruments,
+    remote_debuggers: RemoteDebuggers,
+    debug_mode: bool,
 }
 
 impl MainState {
-    pub fn new(mut ctx: &mut Context, hidpi_factor: f32, rom_data: &[u8]) -> GameResult<MainState> {
+    // everything the window runs with is set up in main
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        ctx: &mut Context,
+        hidpi_factor: f32,
+        rom_data: &[u8],
+        display_settings: DisplaySettings,
+        beeper: Beeper,
+        recorder: Recorder,
+        debugger: Debugger,
+        instruments: Instruments,
+        remote_debuggers: RemoteDebuggers,
+        debug_mode: bool,
+    ) -> GameResult<MainState> {
+        let mut chip8_state = Chip8VM::new(rom_data);
+        chip8_state.record_pixel_sources(debug_mode);
+
         Ok(MainState {
-            debug_run_next: if cfg!(debug_assertions) { false } else { true },
-            imgui_wrapper: ImGuiWrapper::new(&mut ctx),
+            display_settings,
+            framebuffer: Framebuffer::default(),
+            display_image: None,
+            window_size: window_size(display_settings.scale, debug_mode),
+            beeper,
+            recorder,
+            debugger,
+            instruments,
+            remote_debuggers,
+            debug_mode,
+            imgui_wrapper: ImGuiWrapper::new(ctx),
             hidpi_factor,
-            chip8_state: Chip8VM::new(rom_data),
+            chip8_state,
         })
     }
+
+    // Applies the window size and fullscreen state, the layout follows the
+    // new size on the next frame
+    fn set_window_mode(&mut self, ctx: &mut Context) -> GameResult<()> {
+        graphics::set_mode(
+            ctx,
+            window_mode(
+                self.window_size,
+                self.debug_mode,
+                self.display_settings.fullscreen,
+            ),
+        )?;
+        let (width, height) = graphics::drawable_size(ctx);
+        graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height))
+    }
+
+    fn stop_recording(&mut self) {
+        match self.recorder.finish() {
+            Ok(paths) => paths
+                .iter()
+                .for_each(|path| println!("Recording written to {}", path)),
+            Err(error) => println!("{}", error),
+        }
+    }
+
+    // Writes out the reports and recordings, whether the window is closed or
+    // the VM stops it with an error
+    fn shut_down(&mut self) {
+        let profiler = &self.instruments.profiler;
+        if profiler.report_path.is_some() {
+            match profiler.save_report(&self.chip8_state) {
+                Ok(path) => println!("Profile written to {}", path),
+                Err(error) => println!("Error with writing profile: {}", error),
+            }
+        }
+        if profiler.coverage_path.is_some() {
+            match save_coverage(profiler) {
+                Ok(path) => println!("Coverage written to {}", path),
+                Err(error) => println!("Error with writing coverage: {}", error),
+            }
+        }
+        if let Err(error) = self.beeper.finish() {
+            println!("Error with writing audio: {}", error);
+        }
+        self.stop_recording();
+    }
+
+    fn set_debug_mode(&mut self, ctx: &mut Context, debug_mode: bool) -> GameResult<()> {
+        // the panels are added around the game display instead of shrinking it
+        let (width, height) = self.window_size;
+        let (panels_width, panels_height) = DEBUG_PANELS_SIZE;
+        self.window_size = if debug_mode {
+            (width + panels_width, height + panels_height)
+        } else {
+            (width - panels_width, height - panels_height)
+        };
+        self.debug_mode = debug_mode;
+        self.set_window_mode(ctx)?;
+
+        // without the overlay there is no way to step, so don't leave the VM paused
+        if debug_mode {
+            self.instruments.tracer.enabled = true;
+            self.instruments.profiler.enabled = true;
+            self.chip8_state.record_pixel_sources(true);
+        } else {
+            self.debugger.resume();
+        }
+        Ok(())
+    }
 }
 
 impl EventHandler for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        const FPS: u32 = 400;
+        self.remote_debuggers
+            .poll(&mut self.chip8_state, &mut self.debugger);
 
-        while ggez::timer::check_update_time(ctx, FPS) {
-            if self.chip8_state.waiting_for_key_press {
-                if let Some(code) = self.chip8_state.pressed_key {
-                    self.chip8_state.v[self.chip8_state.key_index_store as usize] = code;
-                    self.chip8_state.waiting_for_key_press = false;
-                }
-            } else if self.debug_run_next {
-                let opcode =
-                    fetch_opcode(&self.chip8_state.memory, self.chip8_state.program_counter)
-                        .unwrap();
-                let instruction = decode_opcode(opcode);
-
-                if cfg!(debug_assertions) && opcode != 0 {
-                    println!("[{:04X?}]: {}", opcode, instruction.to_string());
-                }
-                self.chip8_state.execute_instruction(instruction, opcode);
-                if let Some(_code) = self.chip8_state.pressed_key {
-                    //println!("Key Pressed: {:02X?}", code);
-                }
-                if self.chip8_state.delay_timer > 0 {
-                    self.chip8_state.delay_timer -= 1;
-                }
-                if self.chip8_state.sound_timer > 0 {
-                    self.chip8_state.sound_timer -= 1;
+        while ggez::timer::check_update_time(ctx, CYCLES_PER_SECOND) {
+            let instruments = &mut self.instruments;
+            let stepped = match run_cycle(
+                &mut self.chip8_state,
+                &mut self.debugger,
+                &mut self.beeper,
+                &mut self.recorder,
+                |program_counter, opcode, registers, chip8_state, symbols| {
+                    instruments.record(program_counter, opcode, registers, chip8_state, symbols)
+                },
+            ) {
+                Ok(stepped) => stepped,
+                // ggez stops without a quit_event when update fails
+                Err(error) => {
+                    self.shut_down();
+                    return Err(GameError::EventLoopError(error));
                 }
+            };
 
-                self.chip8_state.program_counter += 2;
-
-                if cfg!(debug_assertions) {
-                    self.debug_run_next = false;
+            if stepped {
+                if let Some(unknown) = self.debugger.unknown_opcode_skipped(&self.chip8_state) {
+                    println!(
+                        "Skipped unknown opcode {:04X?} at {:03X?}",
+                        unknown.opcode, unknown.address
+                    );
                 }
             }
+
+            // breaking on an unknown opcode has to show the debugger to be of any use
+            if self.debugger.unknown_opcode_hit.is_some()
+                && !self.debugger.is_running()
+                && !self.debug_mode
+            {
+                self.set_debug_mode(ctx, true)?;
+            }
         }
+        self.beeper.flush();
 
         //println!("FPS: {}", ggez::timer::fps(ctx));
 
@@ -74,43 +206,99 @@ impl EventHandler for MainState {
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        graphics::clear(ctx, graphics::BLACK);
+        let DisplaySettings {
+            palette,
+            anti_flicker,
+            crt,
+            scaling,
+            ..
+        } = self.display_settings;
+        let screen = graphics::screen_coordinates(ctx);
+        let layout = Layout::new((screen.w, screen.h), self.debug_mode, scaling);
+        graphics::clear(ctx, to_color(palette.background()));
 
         // Render game stuff
-        {
-            for x in 0..DISPLAY_SIZE[0] {
-                for y in 0..DISPLAY_SIZE[1] {
-                    if self.chip8_state.display_data[y * DISPLAY_SIZE[0] + x] {
-                        let rect = graphics::Rect::new(
-                            (x * SCALE) as f32,
-                            (y * SCALE) as f32,
-                            (SCALE) as f32,
-                            (SCALE) as f32,
-                        );
-                        let r = graphics::Mesh::new_rectangle(
-                            ctx,
-                            graphics::DrawMode::Fill(graphics::FillOptions::DEFAULT),
-                            rect,
-                            graphics::Color::new(1.0, 1.0, 1.0, 1.0),
-                        )?;
-                        graphics::draw(ctx, &r, graphics::DrawParam::default())?;
-                    }
-                }
+        let changed =
+            self.framebuffer
+                .update(&self.chip8_state.display_data, palette, anti_flicker);
+        let effects = crt.effects();
+        let scale = effects.map_or(1, |_| crt_scale(layout.scale()));
+        let outdated = match &self.display_image {
+            Some((_, image_crt, image_scale)) => {
+                changed || *image_crt != crt || *image_scale != scale
+            }
+            None => true,
+        };
+        if outdated {
+            let (width, height) = (self.framebuffer.width(), self.framebuffer.height());
+            let mut image = match effects {
+                Some(effects) => graphics::Image::from_rgba8(
+                    ctx,
+                    (width * scale) as u16,
+                    (height * scale) as u16,
+                    &effects.apply(self.framebuffer.rgba(), width, height, scale),
+                )?,
+                None => graphics::Image::from_rgba8(
+                    ctx,
+                    width as u16,
+                    height as u16,
+                    self.framebuffer.rgba(),
+                )?,
+            };
+            // the effects are already at about the window's size, stretching
+            // them by a fraction of a pixel is smoother than repeating rows
+            if effects.is_some() && scaling == Scaling::Smooth {
+                image.set_filter(graphics::FilterMode::Linear);
+            } else {
+                image.set_filter(graphics::FilterMode::Nearest);
             }
+            self.display_image = Some((image, crt, scale));
+        }
+        if let Some((image, _, _)) = &self.display_image {
+            let scale = layout.display.size[0] / image.width() as f32;
+            graphics::draw(
+                ctx,
+                image,
+                graphics::DrawParam::default()
+                    .dest(layout.display.position)
+                    .scale([scale, scale]),
+            )?;
         }
 
         // Render game ui
-        if cfg!(debug_assertions) {
-            self.imgui_wrapper
-                .render(ctx, self.hidpi_factor, &self.chip8_state);
+        if self.debug_mode {
+            self.imgui_wrapper.render(
+                ctx,
+                self.hidpi_factor,
+                &layout,
+                &mut self.chip8_state,
+                &mut self.debugger,
+                &mut self.instruments,
+            );
         }
 
         graphics::present(ctx)?;
         Ok(())
     }
 
+    fn quit_event(&mut self, _ctx: &mut Context) -> bool {
+        self.shut_down();
+        false
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        if !self.display_settings.fullscreen {
+            self.window_size = (width, height);
+        }
+        if let Err(error) =
+            graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height))
+        {
+            println!("Error with resizing window: {}", error);
+        }
+    }
+
     fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) {
-        if cfg!(debug_assertions) {
+        if self.debug_mode {
             self.imgui_wrapper.update_mouse_pos(x, y);
         }
     }
@@ -122,7 +310,7 @@ impl EventHandler for MainState {
         _x: f32,
         _y: f32,
     ) {
-        if cfg!(debug_assertions) {
+        if self.debug_mode {
             self.imgui_wrapper.update_mouse_down((
                 button == MouseButton::Left,
                 button == MouseButton::Right,
@@ -138,45 +326,108 @@ impl EventHandler for MainState {
         _x: f32,
         _y: f32,
     ) {
-        if cfg!(debug_assertions) {
+        if self.debug_mode {
             self.imgui_wrapper.update_mouse_down((false, false, false));
         }
     }
 
     fn key_down_event(
         &mut self,
-        _ctx: &mut Context,
+        ctx: &mut Context,
         keycode: KeyCode,
         keymods: KeyMods,
         _repeat: bool,
     ) {
-        if keycode == KeyCode::Space {
-            self.debug_run_next = true;
+        if keycode == KeyCode::F12 {
+            if let Err(error) = self.set_debug_mode(ctx, !self.debug_mode) {
+                println!("Error with resizing window: {}", error);
+            }
+            return;
+        }
+        if keycode == KeyCode::F2 {
+            let path = next_free_path("screenshot", "png");
+            match save_screenshot(
+                &path,
+                &self.chip8_state.display_data,
+                self.display_settings.palette,
+                self.display_settings.screenshot_scale,
+            ) {
+                Ok(()) => println!("Screenshot written to {}", path),
+                Err(error) => println!("{}", error),
+            }
+            return;
+        }
+        if keycode == KeyCode::F3 {
+            if self.recorder.is_recording() {
+                self.stop_recording();
+            } else {
+                let path = next_free_path("recording", "gif");
+                match GifSink::create(&path, self.display_settings.palette) {
+                    Ok(sink) => {
+                        println!("Recording to {}", path);
+                        self.recorder.add_sink(Box::new(sink));
+                    }
+                    Err(error) => println!("{}", error),
+                }
+            }
+            return;
+        }
+        if keycode == KeyCode::F4 {
+            let crt = self.display_settings.crt.next();
+            self.display_settings.crt = crt;
+            println!("CRT effect: {}", crt);
+            return;
+        }
+        if keycode == KeyCode::Return && keymods.contains(KeyMods::ALT) {
+            self.display_settings.fullscreen = !self.display_settings.fullscreen;
+            if let Err(error) = self.set_window_mode(ctx) {
+                println!("Error with switching fullscreen: {}", error);
+            }
+            return;
         }
 
-        self.chip8_state.handle_keyboard_input(keycode);
-
-        if cfg!(debug_assertions) {
+        if self.debug_mode {
             self.imgui_wrapper.update_key_down(keycode, keymods);
+
+            // keys typed into debugger input fields shouldn't reach the VM
+            if self.imgui_wrapper.wants_keyboard() {
+                return;
+            }
+
+            match keycode {
+                KeyCode::F5 if self.debugger.is_running() => self.debugger.pause(),
+                KeyCode::F5 => self.debugger.resume(),
+                KeyCode::Space => self.debugger.step_into(),
+                KeyCode::F10 => self.debugger.step_over(&self.chip8_state),
+                KeyCode::F11 => self.debugger.step_out(&self.chip8_state),
+                _ => (),
+            }
         }
+
+        if keycode == KeyCode::M {
+            self.beeper.toggle_mute();
+            return;
+        }
+
+        self.chip8_state.handle_keyboard_input(keycode);
     }
 
     fn key_up_event(&mut self, _ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
         self.chip8_state.pressed_key = None;
 
-        if cfg!(debug_assertions) {
+        if self.debug_mode {
             self.imgui_wrapper.update_key_up(keycode, keymods);
         }
     }
 
     fn text_input_event(&mut self, _ctx: &mut Context, val: char) {
-        if cfg!(debug_assertions) {
+        if self.debug_mode {
             self.imgui_wrapper.update_text(val);
         }
     }
 
     fn mouse_wheel_event(&mut self, _ctx: &mut Context, x: f32, y: f32) {
-        if cfg!(debug_assertions) {
+        if self.debug_mode {
             self.imgui_wrapper.update_scroll(x, y);
         }
     }
@@ -1,4 +1,13 @@
 use clap::{App, Arg, ArgMatches};
+use std::ops::RangeInclusive;
+
+use crate::audio::{BeeperSettings, Waveform};
+use crate::debug::UnknownOpcodeAction;
+use crate::display::{
+    check_screenshot_scale, AntiFlicker, CrtPreset, DisplaySettings, Palette, Scaling, Theme,
+    DEFAULT_SCALE,
+};
+use crate::utils::parse_address_range;
 
 pub fn setup_cmd_program_arguments() -> ArgMatches<'static> {
     App::new("Chip-8 VM emulator")
@@ -13,12 +22,373 @@ pub fn setup_cmd_program_arguments() -> ArgMatches<'static> {
                 .takes_value(true)
                 .help("The ROM file you want to run in this VM"),
         )
+        .arg(
+            Arg::with_name("theme")
+                .long("theme")
+                .takes_value(true)
+                .possible_values(&["classic", "green", "amber", "lcd", "high-contrast"])
+                .help("Display colours (default: classic)"),
+        )
+        .arg(
+            Arg::with_name("foreground")
+                .long("foreground")
+                .takes_value(true)
+                .value_name("RRGGBB")
+                .help("Colour of lit pixels, overrides the theme"),
+        )
+        .arg(
+            Arg::with_name("background")
+                .long("background")
+                .takes_value(true)
+                .value_name("RRGGBB")
+                .help("Colour of unlit pixels, overrides the theme"),
+        )
+        .arg(
+            Arg::with_name("palette")
+                .long("palette")
+                .takes_value(true)
+                .value_name("COLORS")
+                .help("Four comma separated colours for XO-CHIP bitplanes: background, plane 1, plane 2, both planes"),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .takes_value(true)
+                .help("Window pixels per VM pixel the window opens with (default: 16)"),
+        )
+        .arg(
+            Arg::with_name("scaling")
+                .long("scaling")
+                .takes_value(true)
+                .possible_values(&["integer", "smooth"])
+                .help("Scale the display to a whole number of window pixels or fill the window (default: integer)"),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Start in fullscreen (toggle it with Alt+Enter)"),
+        )
+        .arg(
+            Arg::with_name("screenshot-scale")
+                .long("screenshot-scale")
+                .takes_value(true)
+                .help("Image pixels per VM pixel in screenshots taken with F2 (default: 1)"),
+        )
+        .arg(
+            Arg::with_name("record-gif")
+                .long("record-gif")
+                .takes_value(true)
+                .help("Record the display to this animated GIF, also in --repl runs (start and stop recordings with F3)"),
+        )
+        .arg(
+            Arg::with_name("record-frames")
+                .long("record-frames")
+                .takes_value(true)
+                .help("Record the display to this file as raw 64x32 RGB frames at 50 fps"),
+        )
+        .arg(
+            Arg::with_name("anti-flicker")
+                .long("anti-flicker")
+                .takes_value(true)
+                .possible_values(&["off", "blend", "or", "persistence"])
+                .help("Reduce sprite flicker: average the last two frames, show pixels lit in either of them, or let pixels fade out (default: off)"),
+        )
+        .arg(
+            Arg::with_name("decay")
+                .long("decay")
+                .takes_value(true)
+                .requires("anti-flicker")
+                .help("Percentage of brightness a pixel loses every frame with --anti-flicker=persistence (default: 50)"),
+        )
+        .arg(
+            Arg::with_name("crt")
+                .long("crt")
+                .takes_value(true)
+                .possible_values(&["off", "scanlines", "lcd", "crt"])
+                .help("Post-processing of the display, switch presets with F4 (default: off)"),
+        )
+        .arg(
+            Arg::with_name("no-sound")
+                .long("no-sound")
+                .help("Don't open an audio device"),
+        )
+        .arg(
+            Arg::with_name("audio-out")
+                .long("audio-out")
+                .takes_value(true)
+                .help("Write the beeper output to this WAV file, also in --repl and --no-sound runs"),
+        )
+        .arg(
+            Arg::with_name("mute")
+                .long("mute")
+                .help("Start with the beeper muted (toggle it with M)"),
+        )
+        .arg(
+            Arg::with_name("frequency")
+                .long("frequency")
+                .takes_value(true)
+                .value_name("HZ")
+                .help("Pitch of the beeper tone (default: 440)"),
+        )
+        .arg(
+            Arg::with_name("waveform")
+                .long("waveform")
+                .takes_value(true)
+                .possible_values(&["square", "sine"])
+                .help("Waveform of the beeper tone (default: square)"),
+        )
+        .arg(
+            Arg::with_name("volume")
+                .long("volume")
+                .takes_value(true)
+                .help("Beeper volume from 0 to 100 (default: 25)"),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .short("d")
+                .long("debug")
+                .help("Start paused with the debugger overlay open (toggle it with F12)"),
+        )
+        .arg(
+            Arg::with_name("repl")
+                .long("repl")
+                .conflicts_with_all(&["debug", "gdb", "dap"])
+                .help("Debug in the terminal without opening a window"),
+        )
+        .arg(
+            Arg::with_name("tui")
+                .long("tui")
+                .conflicts_with_all(&["debug", "repl", "gdb", "dap"])
+                .help("Play in the terminal without opening a window"),
+        )
+        .arg(
+            Arg::with_name("gdb")
+                .long("gdb")
+                .takes_value(true)
+                .value_name("PORT")
+                .help("Wait for a GDB remote protocol client on this port (or address:port)"),
+        )
+        .arg(
+            Arg::with_name("dap")
+                .long("dap")
+                .takes_value(true)
+                .value_name("PORT")
+                .help("Wait for a Debug Adapter Protocol client on this port (or address:port)"),
+        )
+        .arg(
+            Arg::with_name("unknown-opcodes")
+                .long("unknown-opcodes")
+                .takes_value(true)
+                .possible_values(&["log", "break", "fatal"])
+                .default_value("log")
+                .help("Print unknown opcodes and carry on, pause in the debugger on them, or exit with an error"),
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .long("symbols")
+                .takes_value(true)
+                .help("Load and save address labels in this symbol file (default: the ROM path with a .sym extension)"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .takes_value(true)
+                .help("Write an execution trace of every instruction to this file"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .help("Count executed instructions and memory accesses, and write a report to this file on exit"),
+        )
+        .arg(
+            Arg::with_name("coverage")
+                .long("coverage")
+                .takes_value(true)
+                .help("Write a map of executed, data and untouched bytes to this file on exit"),
+        )
+        .arg(
+            Arg::with_name("trace-range")
+                .long("trace-range")
+                .takes_value(true)
+                .help("Only trace instructions in this address range, e.g. 0x200-0x300"),
+        )
         .get_matches()
 }
 
-pub fn get_rom_path(matches: ArgMatches) -> Result<String, String> {
+pub fn get_rom_path(matches: &ArgMatches) -> Result<String, String> {
     match matches.value_of("file") {
         Some(value) => Ok(value.to_string()),
         None => Err("Command line argument error".to_string()),
     }
 }
+
+pub fn get_debug_mode(matches: &ArgMatches) -> bool {
+    matches.is_present("debug")
+}
+
+pub fn get_display_settings(matches: &ArgMatches) -> Result<DisplaySettings, String> {
+    let mut palette = match matches.value_of("theme") {
+        Some(name) => Theme::from_name(name)?.palette(),
+        None => Palette::default(),
+    };
+    if let Some(colors) = matches.value_of("palette") {
+        palette = Palette::parse(colors)?;
+    }
+    if let Some(color) = matches.value_of("foreground") {
+        palette.set_foreground(color)?;
+    }
+    if let Some(color) = matches.value_of("background") {
+        palette.set_background(color)?;
+    }
+
+    let decay = match matches.value_of("decay") {
+        Some(value) => match value.parse::<u8>() {
+            Ok(decay) if decay > 0 && decay <= 100 => decay as f32 / 100.0,
+            _ => return Err(format!("Invalid decay '{}'", value)),
+        },
+        None => 0.5,
+    };
+    let anti_flicker = match matches.value_of("anti-flicker") {
+        Some(name) => AntiFlicker::from_name(name, decay)?,
+        None => AntiFlicker::Off,
+    };
+
+    let scale = match matches.value_of("scale") {
+        Some(value) => parse_scale(value)?,
+        None => DEFAULT_SCALE,
+    };
+    let screenshot_scale = match matches.value_of("screenshot-scale") {
+        Some(value) => check_screenshot_scale(parse_scale(value)?)?,
+        None => 1,
+    };
+    let scaling = match matches.value_of("scaling") {
+        Some("smooth") => Scaling::Smooth,
+        _ => Scaling::Integer,
+    };
+
+    let crt = match matches.value_of("crt") {
+        Some(name) => CrtPreset::from_name(name)?,
+        None => CrtPreset::Off,
+    };
+
+    Ok(DisplaySettings {
+        palette,
+        anti_flicker,
+        crt,
+        scale,
+        scaling,
+        fullscreen: matches.is_present("fullscreen"),
+        screenshot_scale,
+    })
+}
+
+fn parse_scale(text: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(scale) if scale > 0 => Ok(scale),
+        _ => Err(format!("Invalid scale '{}'", text)),
+    }
+}
+
+pub fn get_record_gif_path(matches: &ArgMatches) -> Option<String> {
+    matches
+        .value_of("record-gif")
+        .map(|value| value.to_string())
+}
+
+pub fn get_record_frames_path(matches: &ArgMatches) -> Option<String> {
+    matches
+        .value_of("record-frames")
+        .map(|value| value.to_string())
+}
+
+pub fn get_sound_enabled(matches: &ArgMatches) -> bool {
+    !matches.is_present("no-sound")
+}
+
+pub fn get_audio_out_path(matches: &ArgMatches) -> Option<String> {
+    matches.value_of("audio-out").map(|value| value.to_string())
+}
+
+pub fn get_beeper_settings(matches: &ArgMatches) -> Result<BeeperSettings, String> {
+    let mut settings = BeeperSettings {
+        muted: matches.is_present("mute"),
+        ..BeeperSettings::default()
+    };
+
+    if let Some(value) = matches.value_of("frequency") {
+        settings.frequency = match value.parse::<f32>() {
+            Ok(frequency) if frequency > 0.0 => frequency,
+            _ => return Err(format!("Invalid frequency '{}'", value)),
+        };
+    }
+    if let Some("sine") = matches.value_of("waveform") {
+        settings.waveform = Waveform::Sine;
+    }
+    if let Some(value) = matches.value_of("volume") {
+        settings.volume = match value.parse::<u8>() {
+            Ok(volume) if volume <= 100 => volume as f32 / 100.0,
+            _ => return Err(format!("Invalid volume '{}'", value)),
+        };
+    }
+
+    Ok(settings)
+}
+
+// A bare port number listens on localhost only
+fn get_listen_address(matches: &ArgMatches, name: &str) -> Option<String> {
+    matches.value_of(name).map(|value| {
+        if value.contains(':') {
+            value.to_string()
+        } else {
+            format!("127.0.0.1:{}", value)
+        }
+    })
+}
+
+pub fn get_repl_mode(matches: &ArgMatches) -> bool {
+    matches.is_present("repl")
+}
+
+pub fn get_tui_mode(matches: &ArgMatches) -> bool {
+    matches.is_present("tui")
+}
+
+pub fn get_gdb_address(matches: &ArgMatches) -> Option<String> {
+    get_listen_address(matches, "gdb")
+}
+
+pub fn get_dap_address(matches: &ArgMatches) -> Option<String> {
+    get_listen_address(matches, "dap")
+}
+
+pub fn get_unknown_opcode_action(matches: &ArgMatches) -> UnknownOpcodeAction {
+    match matches.value_of("unknown-opcodes") {
+        Some("break") => UnknownOpcodeAction::Break,
+        Some("fatal") => UnknownOpcodeAction::Fatal,
+        _ => UnknownOpcodeAction::Log,
+    }
+}
+
+pub fn get_symbol_path(matches: &ArgMatches) -> Option<String> {
+    matches.value_of("symbols").map(|value| value.to_string())
+}
+
+pub fn get_trace_path(matches: &ArgMatches) -> Option<String> {
+    matches.value_of("trace").map(|value| value.to_string())
+}
+
+pub fn get_trace_range(matches: &ArgMatches) -> Result<Option<RangeInclusive<u16>>, String> {
+    matches
+        .value_of("trace-range")
+        .map(parse_address_range)
+        .transpose()
+}
+
+pub fn get_profile_path(matches: &ArgMatches) -> Option<String> {
+    matches.value_of("profile").map(|value| value.to_string())
+}
+
+pub fn get_coverage_path(matches: &ArgMatches) -> Option<String> {
+    matches.value_of("coverage").map(|value| value.to_string())
+}
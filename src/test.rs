@@ -3,22 +3,46 @@
 #![allow(unused_imports)]
 
 use super::*;
+use crate::audio::{AudioSink, Beeper, BeeperSettings, WavSink, Waveform, SAMPLE_RATE};
+use crate::debug::{
+    call_stack, coverage, coverage_regions, coverage_report, decode_message, default_symbol_path,
+    encode_message, encode_packet, run_cycle, CallFrame, Coverage, CoverageRegion, DapSession,
+    Debugger, GdbStub, MemoryAccess, Profiler, RegisterChange, RegisterSnapshot, Repl, ReplOutput,
+    RunMode, SourceLocation, SourceMap, SubroutineProfile, SymbolTable, TraceEntry, Tracer,
+    UnknownOpcodeAction,
+};
+use crate::display::{
+    crt_scale, min_window_size, save_screenshot, window_size, AntiFlicker, CrtEffects, CrtPreset,
+    Framebuffer, GifSink, Layout, Palette, RawSink, Recorder, Scaling, Theme, DEFAULT_SCALE,
+};
 use crate::instructions::decode_opcode;
+use crate::tui::{screen_lines, Tui, KEY_HOLD_CYCLES};
+use crate::utils::{parse_address, parse_address_range};
+use gif::SetParameter;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::rc::Rc;
+use std::time::Duration;
 
 fn get_vm() -> Chip8VM {
     Chip8VM {
         waiting_for_key_press: false,
         key_index_store: 0x00,
         display_data: [false; DISPLAY_SIZE[0] * DISPLAY_SIZE[1]],
-        memory: [0 as u8; MEMORY_SIZE],
-        v: [0 as u8; 16],
+        memory: [0_u8; MEMORY_SIZE],
+        v: [0_u8; 16],
         i: 0,
         delay_timer: 0,
         sound_timer: 0,
         program_counter: 0x200,
         stack_pointer: 0,
-        stack: [0 as u16; 16],
+        stack: [0_u16; 16],
         pressed_key: None,
+        cycle_count: 0,
+        pixel_sources: None,
+        unknown_opcode: None,
     }
 }
 
@@ -34,7 +58,7 @@ fn test_00E0() {
     vm.execute_instruction(decode_opcode(opcode), opcode);
 
     for i in 0..vm.display_data.len() {
-        assert_eq!(vm.display_data[i], false);
+        assert!(!vm.display_data[i]);
     }
 }
 
@@ -494,6 +518,46 @@ fn test_DXYN() {
     assert_eq!(vm.v[0xF], 0x01);
 }
 
+#[test]
+fn test_pixel_sources() {
+    let opcode = 0xD005;
+    let mut vm = get_vm();
+    load_test_sprite(&mut vm.memory);
+    vm.i = 0xA;
+
+    vm.execute_instruction(decode_opcode(opcode), opcode);
+    assert_eq!(vm.pixel_source(0, 0), None);
+
+    vm.record_pixel_sources(true);
+    vm.program_counter = 0x240;
+    vm.cycle_count = 6;
+    vm.execute_instruction(decode_opcode(0x00E0), 0x00E0);
+    vm.execute_instruction(decode_opcode(opcode), opcode);
+    let first_draw = PixelSource {
+        program_counter: 0x240,
+        i: 0xA,
+        cycle: 7,
+        collision: false,
+    };
+    assert_eq!(vm.pixel_source(0, 0), Some(first_draw));
+    assert_eq!(vm.pixel_source(1, 1), None);
+    assert_eq!(vm.pixel_source(64, 0), None);
+
+    // the 90 row erases pixels 0 and 3 and leaves 1 and 2 alone
+    vm.program_counter = 0x250;
+    vm.i = 0xB;
+    vm.execute_instruction(decode_opcode(0xD001), 0xD001);
+    assert_eq!(vm.v[0xF], 0x01);
+    let second_draw = vm.pixel_source(0, 0).unwrap();
+    assert_eq!(second_draw.program_counter, 0x250);
+    assert!(second_draw.collision);
+    assert_eq!(vm.pixel_source(3, 0), Some(second_draw));
+    assert_eq!(vm.pixel_source(1, 0), Some(first_draw));
+
+    vm.execute_instruction(decode_opcode(0x00E0), 0x00E0);
+    assert_eq!(vm.pixel_source(1, 0), None);
+}
+
 #[test]
 fn test_EX9E() {
     // 0xEX9E - skip the following instruction if the key corresponding to the hex value currently stored in register VX is pressed
@@ -556,7 +620,7 @@ fn test_FX0A() {
     let mut vm = get_vm();
 
     vm.execute_instruction(decode_opcode(opcode), opcode);
-    assert_eq!(vm.waiting_for_key_press, true);
+    assert!(vm.waiting_for_key_press);
 }
 
 #[test]
@@ -674,3 +738,1616 @@ fn test_FX65() {
     assert_eq!(vm.v[0x4], 0x04);
     assert_eq!(vm.i, 0x05);
 }
+
+fn load_test_program(memory: &mut [u8; MEMORY_SIZE]) {
+    let program = [
+        0x22, 0x06, // 200: CALL 206
+        0x61, 0x01, // 202: LD V1, 01
+        0x12, 0x04, // 204: JP 204
+        0x60, 0x05, // 206: LD V0, 05
+        0x22, 0x0C, // 208: CALL 20C
+        0x00, 0xEE, // 20A: RET
+        0x62, 0x07, // 20C: LD V2, 07
+        0x00, 0xEE, // 20E: RET
+    ];
+    memory[0x200..0x200 + program.len()].copy_from_slice(&program);
+}
+
+fn run_debugger(vm: &mut Chip8VM, debugger: &mut Debugger) {
+    let mut steps = 0;
+    while debugger.is_running() {
+        vm.step();
+        debugger.after_step(vm);
+        steps += 1;
+        assert!(steps < 100, "debugger never paused");
+    }
+}
+
+#[test]
+fn test_debugger_step_into() {
+    let mut vm = get_vm();
+    let mut debugger = Debugger::new(true);
+    load_test_program(&mut vm.memory);
+
+    debugger.step_into();
+    run_debugger(&mut vm, &mut debugger);
+
+    assert_eq!(debugger.run_mode, RunMode::Paused);
+    assert_eq!(vm.program_counter, 0x206);
+    assert_eq!(vm.stack_pointer, 1);
+}
+
+#[test]
+fn test_debugger_step_over() {
+    let mut vm = get_vm();
+    let mut debugger = Debugger::new(true);
+    load_test_program(&mut vm.memory);
+
+    debugger.step_over(&vm);
+    run_debugger(&mut vm, &mut debugger);
+
+    assert_eq!(vm.program_counter, 0x202);
+    assert_eq!(vm.stack_pointer, 0);
+    assert_eq!(vm.v[0x0], 0x05);
+    assert_eq!(vm.v[0x2], 0x07);
+
+    // not a subroutine call, so step over behaves like step into
+    debugger.step_over(&vm);
+    run_debugger(&mut vm, &mut debugger);
+
+    assert_eq!(vm.program_counter, 0x204);
+    assert_eq!(vm.v[0x1], 0x01);
+}
+
+#[test]
+fn test_debugger_step_out() {
+    let mut vm = get_vm();
+    let mut debugger = Debugger::new(true);
+    load_test_program(&mut vm.memory);
+
+    // enter both subroutines
+    for _ in 0..3 {
+        vm.step();
+    }
+    assert_eq!(vm.program_counter, 0x20C);
+    assert_eq!(vm.stack_pointer, 2);
+
+    debugger.step_out(&vm);
+    run_debugger(&mut vm, &mut debugger);
+
+    assert_eq!(vm.program_counter, 0x20A);
+    assert_eq!(vm.stack_pointer, 1);
+
+    debugger.step_out(&vm);
+    run_debugger(&mut vm, &mut debugger);
+
+    assert_eq!(vm.program_counter, 0x202);
+    assert_eq!(vm.stack_pointer, 0);
+}
+
+#[test]
+fn test_debugger_run_to_address() {
+    let mut vm = get_vm();
+    let mut debugger = Debugger::new(true);
+    load_test_program(&mut vm.memory);
+
+    debugger.run_to(0x20E);
+    run_debugger(&mut vm, &mut debugger);
+
+    assert_eq!(vm.program_counter, 0x20E);
+    assert_eq!(vm.v[0x2], 0x07);
+    assert_eq!(vm.v[0x1], 0x00);
+}
+
+#[test]
+fn test_debugger_call_stack() {
+    let mut vm = get_vm();
+    load_test_program(&mut vm.memory);
+
+    assert!(call_stack(&vm).is_empty());
+
+    // enter both subroutines
+    for _ in 0..3 {
+        vm.step();
+    }
+
+    assert_eq!(
+        call_stack(&vm),
+        vec![
+            CallFrame {
+                call_address: 0x208,
+                call_opcode: 0x220C,
+                return_address: 0x20A,
+                entry_address: 0x20C,
+            },
+            CallFrame {
+                call_address: 0x200,
+                call_opcode: 0x2206,
+                return_address: 0x202,
+                entry_address: 0x206,
+            },
+        ]
+    );
+}
+
+fn run_traced(vm: &mut Chip8VM, tracer: &mut Tracer, symbols: &SymbolTable, steps: usize) {
+    for _ in 0..steps {
+        let program_counter = vm.program_counter;
+        let registers = RegisterSnapshot::new(vm);
+        let opcode = vm.step();
+        tracer.record(program_counter, opcode, &registers, vm, symbols);
+    }
+}
+
+#[test]
+fn test_trace_register_changes() {
+    let mut vm = get_vm();
+    let mut tracer = Tracer::new(true);
+    load_test_program(&mut vm.memory);
+
+    run_traced(&mut vm, &mut tracer, &SymbolTable::default(), 2);
+
+    let entries: Vec<&TraceEntry> = tracer.entries().iter().collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(
+        *entries[0],
+        TraceEntry {
+            cycle: 1,
+            program_counter: 0x200,
+            opcode: 0x2206,
+            mnemonic: "CALL 206".to_string(),
+            register_changes: vec![RegisterChange {
+                name: "SP",
+                old: 0,
+                new: 1,
+            }],
+        }
+    );
+    assert_eq!(
+        entries[1].to_string(),
+        "       2 206: 6005  LD V0, 05       V0: 00 -> 05"
+    );
+}
+
+#[test]
+fn test_trace_range_and_disable() {
+    let mut vm = get_vm();
+    let mut tracer = Tracer::new(true);
+    tracer.range = Some(0x206..=0x20A);
+    load_test_program(&mut vm.memory);
+
+    run_traced(&mut vm, &mut tracer, &SymbolTable::default(), 4);
+
+    let traced: Vec<u16> = tracer
+        .entries()
+        .iter()
+        .map(|entry| entry.program_counter)
+        .collect();
+    assert_eq!(traced, vec![0x206, 0x208]);
+
+    tracer.enabled = false;
+    run_traced(&mut vm, &mut tracer, &SymbolTable::default(), 2);
+    assert_eq!(tracer.entries().len(), 2);
+
+    // a trace file that can't be created is an error for main to report
+    let error = tracer.set_output_file("no/such/dir/trace.txt").unwrap_err();
+    assert!(error.contains("no/such/dir/trace.txt"), "{}", error);
+    assert!(!tracer.enabled);
+}
+
+#[test]
+fn test_parse_address_range() {
+    assert_eq!(parse_address("0x2A4"), Ok(0x2A4));
+    assert_eq!(parse_address("2a4"), Ok(0x2A4));
+    assert!(parse_address("xyz").is_err());
+
+    assert_eq!(parse_address_range("0x200-0x300"), Ok(0x200..=0x300));
+    assert!(parse_address_range("0x300-0x200").is_err());
+    assert!(parse_address_range("0x200").is_err());
+}
+
+#[test]
+fn test_debugger_breakpoints() {
+    let mut vm = get_vm();
+    let mut debugger = Debugger::new(false);
+    load_test_program(&mut vm.memory);
+
+    debugger.toggle_breakpoint(0x20C);
+    run_debugger(&mut vm, &mut debugger);
+    assert_eq!(vm.program_counter, 0x20C);
+
+    // continuing from a breakpoint shouldn't stop on it again
+    debugger.toggle_breakpoint(0x202);
+    debugger.resume();
+    run_debugger(&mut vm, &mut debugger);
+    assert_eq!(vm.program_counter, 0x202);
+
+    debugger.toggle_breakpoint(0x202);
+    assert!(!debugger.breakpoints.contains(&0x202));
+}
+
+struct GdbTestClient {
+    stream: TcpStream,
+}
+
+impl GdbTestClient {
+    // Sends a packet and keeps the VM running until the reply arrives
+    fn request(
+        &mut self,
+        data: &str,
+        gdb_stub: &mut GdbStub,
+        vm: &mut Chip8VM,
+        debugger: &mut Debugger,
+    ) -> String {
+        self.stream
+            .write_all(encode_packet(data).as_bytes())
+            .unwrap();
+
+        let mut received = String::new();
+        for _ in 0..1000 {
+            gdb_stub.poll(vm, debugger);
+            if debugger.is_running() {
+                vm.step();
+                debugger.after_step(vm);
+            }
+
+            let mut buffer = [0_u8; 256];
+            if let Ok(size) = self.stream.read(&mut buffer) {
+                received.push_str(&String::from_utf8_lossy(&buffer[..size]));
+            }
+            if let Some(start) = received.find('$') {
+                if let Some(end) = received[start..].find('#') {
+                    assert!(received.starts_with('+'));
+                    return received[start + 1..start + end].to_string();
+                }
+            }
+        }
+        panic!("no reply to '{}'", data);
+    }
+}
+
+#[test]
+fn test_gdb_stub() {
+    let mut vm = get_vm();
+    let mut debugger = Debugger::new(false);
+    load_test_program(&mut vm.memory);
+
+    let mut gdb_stub = GdbStub::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(gdb_stub.local_address().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(1)))
+        .unwrap();
+    let mut client = GdbTestClient { stream };
+    let mut request = |data: &str, vm: &mut Chip8VM, debugger: &mut Debugger| {
+        client.request(data, &mut gdb_stub, vm, debugger)
+    };
+
+    assert_eq!(request("?", &mut vm, &mut debugger), "S05");
+    assert!(!debugger.is_running());
+
+    assert_eq!(request("m200,4", &mut vm, &mut debugger), "22066101");
+    assert_eq!(request("mFFE,4", &mut vm, &mut debugger), "E01");
+    assert_eq!(
+        request("m1,ffffffffffffffff", &mut vm, &mut debugger),
+        "E01"
+    );
+    assert_eq!(request("M300,2:abcd", &mut vm, &mut debugger), "OK");
+    assert_eq!(vm.memory[0x300..0x302], [0xAB, 0xCD]);
+
+    assert_eq!(request("P3=7f", &mut vm, &mut debugger), "OK");
+    assert_eq!(request("p3", &mut vm, &mut debugger), "7f");
+    // values outside memory are ignored for I, like for PC
+    let i = vm.i;
+    assert_eq!(request("P10=ffff", &mut vm, &mut debugger), "OK");
+    assert_eq!(vm.i, i);
+    assert_eq!(request("p11", &mut vm, &mut debugger), "0200");
+
+    assert_eq!(request("s", &mut vm, &mut debugger), "S05");
+    assert_eq!(vm.program_counter, 0x206);
+
+    assert_eq!(request("Z0,20c,2", &mut vm, &mut debugger), "OK");
+    assert_eq!(request("c", &mut vm, &mut debugger), "S05");
+    assert_eq!(vm.program_counter, 0x20C);
+    assert_eq!(request("z0,20c,2", &mut vm, &mut debugger), "OK");
+    assert!(debugger.breakpoints.is_empty());
+
+    let registers = request("g", &mut vm, &mut debugger);
+    assert_eq!(registers.len(), 2 * (16 + 2 + 2 + 1 + 1 + 1));
+    assert_eq!(&registers[0..8], "0500007f");
+    assert_eq!(&registers[32..40], "0000020c");
+
+    // a G packet has to set every register
+    assert_eq!(request("G0102", &mut vm, &mut debugger), "E01");
+    assert_eq!(vm.v[0x0], 0x05);
+    assert_eq!(
+        request(&format!("G09{}", &registers[2..]), &mut vm, &mut debugger),
+        "OK"
+    );
+    assert_eq!(vm.v[0x0], 0x09);
+    assert_eq!(vm.program_counter, 0x20C);
+
+    assert!(
+        request("qSupported:xmlRegisters=i386", &mut vm, &mut debugger)
+            .contains("qXfer:features:read+")
+    );
+    let description = request(
+        "qXfer:features:read:target.xml:0,1000",
+        &mut vm,
+        &mut debugger,
+    );
+    assert!(description.starts_with("l<?xml"));
+    assert_eq!(description.matches("<reg ").count(), 21);
+    assert!(description.contains(r#"<reg name="pc" bitsize="16" regnum="17" type="code_ptr"/>"#));
+    let first_part = request("qXfer:features:read:target.xml:0,8", &mut vm, &mut debugger);
+    assert_eq!(first_part, format!("m{}", &description[1..9]));
+    assert_eq!(
+        request("qXfer:features:read:other.xml:0,10", &mut vm, &mut debugger),
+        "E01"
+    );
+
+    assert_eq!(request("vMustReplyEmpty", &mut vm, &mut debugger), "");
+}
+
+#[test]
+fn test_source_map() {
+    let source_map = SourceMap::parse(
+        "# generated by the assembler\n\
+         0x200 src/game.8o:3\n\
+         0x202 src/game.8o:4\n\
+         \n\
+         204 src/game.8o:4\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        source_map.location(0x202),
+        Some(&SourceLocation {
+            path: "src/game.8o".to_string(),
+            line: 4,
+        })
+    );
+    assert_eq!(source_map.location(0x206), None);
+    assert_eq!(
+        source_map.addresses("/home/user/project/src/game.8o", 4),
+        vec![0x202, 0x204]
+    );
+    assert!(source_map.addresses("/home/user/other.8o", 4).is_empty());
+
+    assert!(SourceMap::parse("0x200 game.8o").is_err());
+    assert!(SourceMap::parse("game.8o:3").is_err());
+}
+
+#[test]
+fn test_dap_message_framing() {
+    let message = json!({ "seq": 1, "type": "request", "command": "threads" });
+    let mut encoded = encode_message(&message).into_bytes();
+    assert!(encoded.starts_with(b"Content-Length: 46\r\n\r\n"));
+
+    // incomplete messages are left in the buffer
+    assert_eq!(decode_message(&encoded[..encoded.len() - 1]), None);
+
+    let size = encoded.len();
+    encoded.extend_from_slice(b"Content-Length: 2");
+    assert_eq!(decode_message(&encoded), Some((message, size)));
+
+    // a length that doesn't fit in memory is never complete
+    let huge = format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX);
+    assert_eq!(decode_message(huge.as_bytes()), None);
+}
+
+struct DapTestClient {
+    session: DapSession,
+    sequence: u64,
+}
+
+impl DapTestClient {
+    // Sends a request and returns its response, followed by any events
+    fn request(
+        &mut self,
+        command: &str,
+        arguments: Value,
+        vm: &mut Chip8VM,
+        debugger: &mut Debugger,
+    ) -> Vec<Value> {
+        self.sequence += 1;
+        let request = json!({
+            "seq": self.sequence,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+
+        let messages = self.session.handle_message(&request, vm, debugger);
+        assert_eq!(messages[0]["request_seq"], self.sequence);
+        assert_eq!(messages[0]["command"], command);
+        messages
+    }
+
+    // Keeps the VM running until the session reports that it stopped
+    fn wait_for_stop(&mut self, vm: &mut Chip8VM, debugger: &mut Debugger) -> Value {
+        for _ in 0..1000 {
+            if let Some(event) = self.session.poll_stop(vm, debugger) {
+                return event;
+            }
+            vm.step();
+            debugger.after_step(vm);
+        }
+        panic!("the VM didn't stop");
+    }
+}
+
+#[test]
+fn test_dap_session() {
+    let mut vm = get_vm();
+    let mut debugger = Debugger::new(true);
+    load_test_program(&mut vm.memory);
+
+    let mut client = DapTestClient {
+        session: DapSession::default(),
+        sequence: 0,
+    };
+
+    let messages = client.request("initialize", json!({}), &mut vm, &mut debugger);
+    assert_eq!(messages[0]["success"], true);
+    assert_eq!(messages[0]["body"]["supportsReadMemoryRequest"], true);
+    assert_eq!(messages[1]["event"], "initialized");
+
+    // a bad ROM path fails the request instead of the emulator
+    let messages = client.request(
+        "launch",
+        json!({ "program": "no/such/rom.ch8" }),
+        &mut vm,
+        &mut debugger,
+    );
+    assert_eq!(messages[0]["success"], false);
+    assert!(messages[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("no/such/rom.ch8"));
+    assert_eq!(vm.memory[0x200], 0x22);
+
+    // the launched ROM replaces the VM, but not what the window records
+    let rom = TempFile::new("launch.ch8");
+    std::fs::write(&rom.path, [0x60, 0x01]).unwrap();
+    vm.record_pixel_sources(true);
+    let messages = client.request(
+        "launch",
+        json!({ "program": rom.path }),
+        &mut vm,
+        &mut debugger,
+    );
+    assert_eq!(messages[0]["success"], true);
+    assert_eq!(vm.memory[0x200..0x202], [0x60, 0x01]);
+    assert!(vm.pixel_sources.is_some());
+    load_test_program(&mut vm.memory);
+
+    let messages = client.request(
+        "setInstructionBreakpoints",
+        json!({ "breakpoints": [{ "instructionReference": "0x20C" }, { "instructionReference": "zz" }] }),
+        &mut vm,
+        &mut debugger,
+    );
+    assert_eq!(messages[0]["body"]["breakpoints"][0]["verified"], true);
+    assert_eq!(messages[0]["body"]["breakpoints"][1]["verified"], false);
+    assert!(debugger.breakpoints.contains(&0x20C));
+
+    client.request("configurationDone", json!({}), &mut vm, &mut debugger);
+    assert!(debugger.is_running());
+    let event = client.wait_for_stop(&mut vm, &mut debugger);
+    assert_eq!(event["body"]["reason"], "breakpoint");
+    assert_eq!(vm.program_counter, 0x20C);
+
+    let messages = client.request("stackTrace", json!({}), &mut vm, &mut debugger);
+    let frames = &messages[0]["body"]["stackFrames"];
+    assert_eq!(messages[0]["body"]["totalFrames"], 3);
+    assert_eq!(frames[0]["name"], "sub_20C");
+    assert_eq!(frames[0]["instructionPointerReference"], "0x20C");
+    assert_eq!(frames[1]["name"], "sub_206");
+    assert_eq!(frames[1]["instructionPointerReference"], "0x208");
+    assert_eq!(frames[2]["name"], "main");
+
+    let messages = client.request(
+        "variables",
+        json!({ "variablesReference": 1 }),
+        &mut vm,
+        &mut debugger,
+    );
+    let variables = &messages[0]["body"]["variables"];
+    assert_eq!(variables[0]["name"], "V0");
+    assert_eq!(variables[0]["value"], "0x05");
+    assert_eq!(variables[17]["name"], "PC");
+    assert_eq!(variables[17]["memoryReference"], "0x20C");
+
+    let messages = client.request(
+        "setVariable",
+        json!({ "variablesReference": 1, "name": "V3", "value": "0x7F" }),
+        &mut vm,
+        &mut debugger,
+    );
+    assert_eq!(messages[0]["success"], true);
+    assert_eq!(vm.v[3], 0x7F);
+    let messages = client.request(
+        "setVariable",
+        json!({ "variablesReference": 1, "name": "I", "value": "0xFFFF" }),
+        &mut vm,
+        &mut debugger,
+    );
+    assert_eq!(messages[0]["success"], false);
+
+    // return addresses can be changed, but only those on the stack
+    let mut set_stack = |vm: &mut Chip8VM, name: &str, value: &str| {
+        let messages = client.request(
+            "setVariable",
+            json!({ "variablesReference": 2, "name": name, "value": value }),
+            vm,
+            &mut debugger,
+        );
+        messages[0]["success"] == true
+    };
+    let return_address = format!("{:#X}", vm.stack[0]);
+    assert!(set_stack(&mut vm, "[0]", "0x300"));
+    assert_eq!(vm.stack[0], 0x300);
+    let past_top = format!("[{}]", vm.stack_pointer);
+    assert!(!set_stack(&mut vm, &past_top, "0x300"));
+    assert!(!set_stack(&mut vm, "[0]", "0x1000"));
+    assert!(set_stack(&mut vm, "[0]", &return_address));
+
+    client.request("stepOut", json!({ "threadId": 1 }), &mut vm, &mut debugger);
+    let event = client.wait_for_stop(&mut vm, &mut debugger);
+    assert_eq!(event["body"]["reason"], "step");
+    assert_eq!(vm.program_counter, 0x20A);
+
+    let messages = client.request(
+        "readMemory",
+        json!({ "memoryReference": "0x200", "count": 4 }),
+        &mut vm,
+        &mut debugger,
+    );
+    assert_eq!(
+        messages[0]["body"]["data"],
+        base64::encode(&[0x22, 0x06, 0x61, 0x01])
+    );
+    let messages = client.request(
+        "readMemory",
+        json!({ "memoryReference": "0xFFE", "count": u64::MAX }),
+        &mut vm,
+        &mut debugger,
+    );
+    assert_eq!(messages[0]["body"]["data"], base64::encode(&[0, 0]));
+
+    client.request(
+        "writeMemory",
+        json!({ "memoryReference": "0x300", "data": base64::encode(&[0xAB, 0xCD]) }),
+        &mut vm,
+        &mut debugger,
+    );
+    assert_eq!(vm.memory[0x300..0x302], [0xAB, 0xCD]);
+
+    let messages = client.request(
+        "disassemble",
+        json!({ "memoryReference": "0x200", "instructionCount": 2 }),
+        &mut vm,
+        &mut debugger,
+    );
+    let instructions = &messages[0]["body"]["instructions"];
+    assert_eq!(instructions[0]["instruction"], "CALL 206");
+    assert_eq!(instructions[1]["address"], "0x202");
+
+    let messages = client.request(
+        "disassemble",
+        json!({
+            "memoryReference": "0x200",
+            "offset": i64::MAX,
+            "instructionCount": i64::MAX,
+        }),
+        &mut vm,
+        &mut debugger,
+    );
+    let instructions = messages[0]["body"]["instructions"].as_array().unwrap();
+    assert_eq!(instructions.len(), MEMORY_SIZE / 2);
+    assert_eq!(instructions[0]["instruction"], "??");
+
+    let messages = client.request("evaluate", json!({}), &mut vm, &mut debugger);
+    assert_eq!(messages[0]["success"], false);
+
+    client.request("disconnect", json!({}), &mut vm, &mut debugger);
+    assert!(debugger.is_running());
+}
+
+#[test]
+fn test_unknown_opcodes() {
+    let mut vm = get_vm();
+    // SYS 123; LD V0, 01; blank memory
+    vm.memory[0x200..0x204].copy_from_slice(&[0x01, 0x23, 0x60, 0x01]);
+    let unknown = UnknownOpcode {
+        address: 0x200,
+        opcode: 0x0123,
+    };
+
+    vm.step();
+    assert_eq!(vm.unknown_opcode, Some(unknown));
+    vm.step();
+    assert_eq!(vm.unknown_opcode, None);
+    vm.step();
+    assert_eq!(vm.unknown_opcode, None);
+
+    // the debugger checks the opcode it's about to run after every step
+    let mut debugger = Debugger::new(false);
+    vm.program_counter = 0x200;
+    debugger.after_step(&vm);
+    assert!(debugger.is_running());
+    assert_eq!(debugger.unknown_opcode_hit, None);
+
+    // it stops in front of the opcode, and skips it when resumed
+    debugger.on_unknown_opcode = UnknownOpcodeAction::Break;
+    debugger.after_step(&vm);
+    assert!(!debugger.is_running());
+    assert_eq!(debugger.unknown_opcode_hit, Some(unknown));
+    assert_eq!(vm.program_counter, 0x200);
+    debugger.step_into();
+    vm.step();
+    debugger.after_step(&vm);
+    assert_eq!(debugger.unknown_opcode_hit, None);
+    assert_eq!(vm.program_counter, 0x202);
+
+    // only breaking reports the opcode to debugger clients
+    debugger.on_unknown_opcode = UnknownOpcodeAction::Log;
+    vm.program_counter = 0x1FE;
+    debugger.step_into();
+    vm.step();
+    debugger.after_step(&vm);
+    assert_eq!(debugger.unknown_opcode_hit, None);
+
+    vm.program_counter = 0x200;
+    vm.step();
+    debugger.on_unknown_opcode = UnknownOpcodeAction::Fatal;
+    assert_eq!(
+        debugger.unknown_opcode_error(&vm),
+        Some("Unknown opcode 0123 at 200".to_string())
+    );
+}
+
+#[test]
+fn test_repl_unknown_opcodes() {
+    let repl_with = |on_unknown_opcode| {
+        let mut vm = get_vm();
+        // LD V0, 01; SYS 123; JP 204
+        vm.memory[0x200..0x206].copy_from_slice(&[0x60, 0x01, 0x01, 0x23, 0x12, 0x04]);
+        let mut debugger = Debugger::new(true);
+        debugger.on_unknown_opcode = on_unknown_opcode;
+        Repl::new(vm, debugger, Tracer::new(false))
+    };
+
+    let mut repl = repl_with(UnknownOpcodeAction::Break);
+    assert_eq!(
+        repl_command(&mut repl, "continue"),
+        "Unknown opcode 0123 at 202\n202: 0123  SYS 123"
+    );
+    // stepping on skips it
+    assert_eq!(repl_command(&mut repl, "step 5"), "204: 1204  JP 204");
+
+    let mut repl = repl_with(UnknownOpcodeAction::Fatal);
+    assert_eq!(
+        repl_command(&mut repl, "continue"),
+        "Fatal: Unknown opcode 0123 at 202"
+    );
+
+    // logging reports it in the output instead of the VM printing it
+    let mut repl = repl_with(UnknownOpcodeAction::Log);
+    assert_eq!(
+        repl_command(&mut repl, "step 2"),
+        "Skipped unknown opcode 0123 at 202\n204: 1204  JP 204"
+    );
+}
+
+#[test]
+fn test_debugger_watchpoints() {
+    let mut vm = get_vm();
+    let mut debugger = Debugger::new(false);
+    load_test_program(&mut vm.memory);
+    // LD I, 300; LD V0, 05 ... LD B, V0 writes 0, 0, 5 to 300-302
+    vm.memory[0x20C..0x212].copy_from_slice(&[0xA3, 0x00, 0xF0, 0x33, 0x00, 0xEE]);
+
+    debugger.add_watchpoint(0x302, &vm);
+    run_debugger(&mut vm, &mut debugger);
+    assert_eq!(vm.program_counter, 0x210);
+    let hit = debugger.watchpoint_hit.unwrap();
+    assert_eq!((hit.address, hit.old, hit.new), (0x302, 0x00, 0x05));
+
+    // the same value again isn't a change
+    debugger.resume();
+    vm.program_counter = 0x20E;
+    vm.step();
+    debugger.after_step(&vm);
+    assert!(debugger.is_running());
+    assert_eq!(debugger.watchpoint_hit, None);
+
+    assert!(debugger.remove_watchpoint(0x302));
+    assert!(!debugger.remove_watchpoint(0x302));
+}
+
+fn repl_command(repl: &mut Repl, line: &str) -> String {
+    match repl.execute(line) {
+        Ok(ReplOutput::Text(text)) => text,
+        Ok(ReplOutput::Quit) => "quit".to_string(),
+        Ok(ReplOutput::Fatal(error)) => format!("Fatal: {}", error),
+        Err(error) => format!("Error: {}", error),
+    }
+}
+
+#[test]
+fn test_repl() {
+    let mut vm = get_vm();
+    load_test_program(&mut vm.memory);
+    let mut repl = Repl::new(vm, Debugger::new(true), Tracer::new(false));
+
+    assert_eq!(repl_command(&mut repl, "break 0x20C"), "Breakpoint at 20C");
+    assert_eq!(
+        repl_command(&mut repl, "continue"),
+        "Breakpoint at 20C\n20C: 6207  LD V2, 07"
+    );
+    assert_eq!(repl_command(&mut repl, "stack").lines().count(), 2);
+
+    assert_eq!(repl_command(&mut repl, "finish"), "20A: 00EE  RET");
+    assert_eq!(repl_command(&mut repl, "step"), "202: 6101  LD V1, 01");
+    // an empty line repeats the last command
+    assert_eq!(repl_command(&mut repl, ""), "204: 1204  JP 204");
+    assert_eq!(repl_command(&mut repl, "step 3"), "204: 1204  JP 204");
+
+    assert_eq!(repl_command(&mut repl, "set v3 0x10"), "V3 = 10");
+    assert_eq!(repl.chip8_state.v[3], 0x10);
+    // I has to point into memory, like PC
+    assert_eq!(
+        repl_command(&mut repl, "set i ffff"),
+        "Error: Can't set 'i'"
+    );
+    assert_eq!(repl_command(&mut repl, "set [0x300] ab"), "[300] = AB");
+    assert!(repl_command(&mut repl, "regs").contains("V3: 10"));
+    assert_eq!(
+        repl_command(&mut repl, "x/10 0x2FE"),
+        "2FE: 00 00 AB 00 00 00 00 00\n306: 00 00"
+    );
+    assert_eq!(
+        repl_command(&mut repl, "x/18446744073709551615 0xFFE"),
+        "FFE: 00 00"
+    );
+    assert_eq!(
+        repl_command(&mut repl, "disasm 0x200 2"),
+        "   200: 2206  CALL 206\n   202: 6101  LD V1, 01"
+    );
+
+    repl.chip8_state.program_counter = 0x200;
+    assert_eq!(repl_command(&mut repl, "trace on"), "Tracing on");
+    assert_eq!(
+        repl_command(&mut repl, "step"),
+        "      11 200: 2206  CALL 206        SP: 00 -> 01\n206: 6005  LD V0, 05"
+    );
+
+    assert_eq!(repl_command(&mut repl, "watch 0x300"), "Watchpoint at 300");
+    repl.chip8_state.memory[0x20C..0x212].copy_from_slice(&[0xA3, 0x00, 0xF0, 0x55, 0x00, 0xEE]);
+    repl_command(&mut repl, "trace off");
+    assert_eq!(
+        repl_command(&mut repl, "delete 20c"),
+        "Deleted breakpoint at 20C"
+    );
+    repl_command(&mut repl, "step");
+    repl_command(&mut repl, "set v0 0x42");
+    assert_eq!(
+        repl_command(&mut repl, "continue"),
+        "Watchpoint 300: AB -> 42\n210: 00EE  RET"
+    );
+
+    assert!(repl_command(&mut repl, "bogus").starts_with("Error"));
+    assert!(repl_command(&mut repl, "x/4 0x1000").starts_with("Error"));
+    assert_eq!(repl_command(&mut repl, "quit"), "quit");
+}
+
+fn run_profiled(vm: &mut Chip8VM, profiler: &mut Profiler, steps: usize) {
+    for _ in 0..steps {
+        let registers = RegisterSnapshot::new(vm);
+        let program_counter = vm.program_counter;
+        let opcode = vm.step();
+        profiler.record(program_counter, opcode, &registers, vm);
+    }
+}
+
+#[test]
+fn test_profiler() {
+    let mut vm = get_vm();
+    let mut profiler = Profiler::new(true);
+    load_test_program(&mut vm.memory);
+    // sub_20C: LD I, 300; LD [I], V1; DRW V0, V0, 2; RET
+    vm.memory[0x20C..0x214].copy_from_slice(&[0xA3, 0x00, 0xF1, 0x55, 0xD0, 0x02, 0x00, 0xEE]);
+
+    // through both subroutines, then 11 turns of the JP 204 loop
+    run_profiled(&mut vm, &mut profiler, 20);
+    assert_eq!(profiler.total_cycles(), 20);
+
+    let executed = profiler.counts(MemoryAccess::Execute);
+    assert_eq!(executed[0x200..0x208], [1, 1, 1, 1, 11, 11, 1, 1]);
+    assert_eq!(executed[0x214], 0);
+
+    // LD [I], V1 writes V0 and V1 and moves I past them, DRW reads the next two bytes
+    assert_eq!(
+        profiler.counts(MemoryAccess::Write)[0x2FF..0x305],
+        [0, 1, 1, 0, 0, 0]
+    );
+    assert_eq!(
+        profiler.counts(MemoryAccess::Read)[0x2FF..0x305],
+        [0, 0, 0, 1, 1, 0]
+    );
+
+    assert_eq!(
+        profiler.hottest_subroutines(),
+        vec![
+            SubroutineProfile {
+                entry_address: 0x200,
+                calls: 0,
+                cycles: 13
+            },
+            SubroutineProfile {
+                entry_address: 0x20C,
+                calls: 1,
+                cycles: 4
+            },
+            SubroutineProfile {
+                entry_address: 0x206,
+                calls: 1,
+                cycles: 3
+            },
+        ]
+    );
+    assert_eq!(profiler.hottest_instructions()[0], (0x204, 11));
+    assert_eq!(profiler.hottest_instructions()[1], (0x200, 1));
+    assert_eq!(profiler.hottest_instructions()[2], (0x202, 1));
+
+    let report = profiler.report(&vm);
+    assert!(report.starts_with("Profile of 20 instructions"));
+    assert!(report.contains("  main            0              13   65.0%"));
+    assert!(report.contains("  204           11   55.0%  JP 204"));
+
+    // nothing is counted while disabled, and clearing keeps the settings
+    profiler.set_report_file("profile.txt");
+    profiler.enabled = false;
+    run_profiled(&mut vm, &mut profiler, 5);
+    assert_eq!(profiler.total_cycles(), 20);
+    profiler.clear();
+    assert_eq!(profiler.total_cycles(), 0);
+    assert_eq!(profiler.report_path, Some("profile.txt".to_string()));
+}
+
+#[test]
+fn test_coverage() {
+    let mut vm = get_vm();
+    let mut profiler = Profiler::new(true);
+    load_test_program(&mut vm.memory);
+    // sub_20C: LD I, 300; LD [I], V1; DRW V0, V0, 2; RET
+    vm.memory[0x20C..0x214].copy_from_slice(&[0xA3, 0x00, 0xF1, 0x55, 0xD0, 0x02, 0x00, 0xEE]);
+    run_profiled(&mut vm, &mut profiler, 10);
+
+    assert_eq!(coverage(&profiler, 0x20C), Coverage::Code);
+    assert_eq!(coverage(&profiler, 0x301), Coverage::Data);
+    assert_eq!(coverage(&profiler, 0x303), Coverage::Data);
+    assert_eq!(coverage(&profiler, 0x304), Coverage::Untouched);
+
+    let regions = coverage_regions(&profiler);
+    assert_eq!(
+        regions[1..4],
+        [
+            CoverageRegion {
+                start: 0x200,
+                end: 0x213,
+                coverage: Coverage::Code,
+            },
+            CoverageRegion {
+                start: 0x214,
+                end: 0x2FF,
+                coverage: Coverage::Untouched,
+            },
+            CoverageRegion {
+                start: 0x300,
+                end: 0x303,
+                coverage: Coverage::Data,
+            },
+        ]
+    );
+    assert_eq!(regions.last().unwrap().end as usize, MEMORY_SIZE - 1);
+
+    let report = coverage_report(&profiler);
+    assert!(report.starts_with("# 20 code bytes, 4 data bytes, 4072 untouched bytes\n"));
+    assert!(report.contains("\n200-213 code\n214-2FF untouched\n300-303 data\n"));
+
+    profiler.clear();
+    assert_eq!(coverage(&profiler, 0x20C), Coverage::Untouched);
+}
+
+#[test]
+fn test_symbol_table() {
+    let text = "\
+# pong symbols
+0x206 init
+20C set_score ; V2 = score
+";
+    let mut symbols = SymbolTable::parse(text).unwrap();
+    assert_eq!(symbols.name(0x206), Some("init"));
+    assert_eq!(
+        symbols.get(0x20C).unwrap().comment.as_deref(),
+        Some("V2 = score")
+    );
+    assert_eq!(symbols.label(0x20C), "set_score");
+    assert_eq!(symbols.label(0x20E), "20E");
+    assert_eq!(symbols.resolve("init"), Ok(0x206));
+    assert_eq!(symbols.resolve("0x300"), Ok(0x300));
+    assert!(symbols.resolve("nowhere").is_err());
+
+    assert_eq!(symbols.mnemonic(&decode_opcode(0x220C)), "CALL set_score");
+    assert_eq!(symbols.mnemonic(&decode_opcode(0x1206)), "JP init");
+    assert_eq!(symbols.mnemonic(&decode_opcode(0x1204)), "JP 204");
+    assert_eq!(symbols.mnemonic(&decode_opcode(0x620C)), "LD V2, 0C");
+
+    symbols.insert(0x204, "main_loop", None).unwrap();
+    assert!(symbols.insert(0x204, "two words", None).is_err());
+    assert_eq!(
+        symbols.to_text(),
+        "204 main_loop\n206 init\n20C set_score ; V2 = score\n"
+    );
+    let reloaded = SymbolTable::parse(&symbols.to_text()).unwrap();
+    assert_eq!(reloaded.to_text(), symbols.to_text());
+    assert!(symbols.remove(0x204).is_some());
+    assert!(symbols.save().is_err());
+
+    assert!(SymbolTable::parse("200 main extra").is_err());
+    assert!(SymbolTable::parse("zzz main").is_err());
+    assert_eq!(default_symbol_path("roms/PONG"), "roms/PONG.sym");
+
+    let mut vm = get_vm();
+    let mut tracer = Tracer::new(true);
+    load_test_program(&mut vm.memory);
+    run_traced(&mut vm, &mut tracer, &symbols, 1);
+    assert_eq!(tracer.entries()[0].mnemonic, "CALL init");
+}
+
+#[test]
+fn test_repl_labels() {
+    let mut vm = get_vm();
+    load_test_program(&mut vm.memory);
+    let mut repl = Repl::new(vm, Debugger::new(true), Tracer::new(false));
+
+    assert_eq!(repl_command(&mut repl, "labels"), "No labels");
+    assert_eq!(
+        repl_command(&mut repl, "label 0x20C set_score ; V2 = score"),
+        "20C is now set_score"
+    );
+    assert_eq!(
+        repl_command(&mut repl, "break set_score"),
+        "Breakpoint at set_score"
+    );
+    assert_eq!(repl_command(&mut repl, "break"), "set_score");
+    assert_eq!(
+        repl_command(&mut repl, "continue"),
+        "Breakpoint at set_score\n20C: 6207  LD V2, 07"
+    );
+    assert!(repl_command(&mut repl, "stack").starts_with("#0 set_score called from 208"));
+    assert_eq!(
+        repl_command(&mut repl, "disasm 0x208 3"),
+        "   208: 220C  CALL set_score\n   20A: 00EE  RET\nset_score:  ; V2 = score\n=> 20C: 6207  LD V2, 07"
+    );
+    assert_eq!(
+        repl_command(&mut repl, "labels"),
+        "20C set_score ; V2 = score"
+    );
+    assert!(repl_command(&mut repl, "labels save").starts_with("Error"));
+    assert_eq!(
+        repl_command(&mut repl, "unlabel set_score"),
+        "Deleted label set_score at 20C"
+    );
+    assert_eq!(repl_command(&mut repl, "break"), "20C");
+    assert!(repl_command(&mut repl, "label 0x20C").starts_with("Error"));
+}
+
+#[test]
+fn test_repl_pixel() {
+    let mut vm = get_vm();
+    load_test_sprite(&mut vm.memory);
+    // LD I, 00A; DRW V0, V0, 5
+    vm.memory[0x200..0x204].copy_from_slice(&[0xA0, 0x0A, 0xD0, 0x05]);
+    let mut repl = Repl::new(vm, Debugger::new(true), Tracer::new(false));
+
+    repl_command(&mut repl, "label 0x202 draw_box");
+    repl_command(&mut repl, "step 2");
+    assert_eq!(
+        repl_command(&mut repl, "pixel 0 0"),
+        "Pixel 0, 0 is on, toggled by DRW at draw_box in cycle 2 with I = 00A"
+    );
+    assert_eq!(
+        repl_command(&mut repl, "pixel 1 1"),
+        "Pixel 1, 1 is off, not drawn since the last CLS"
+    );
+    assert!(repl_command(&mut repl, "pixel 64 0").starts_with("Error"));
+}
+
+// Keeps everything written to it for the test to look at
+struct RecordingSink(Rc<RefCell<Vec<f32>>>);
+
+impl AudioSink for RecordingSink {
+    fn write(&mut self, samples: &[f32]) {
+        self.0.borrow_mut().extend_from_slice(samples);
+    }
+}
+
+#[test]
+fn test_beeper() {
+    let samples = Rc::new(RefCell::new(Vec::new()));
+    let settings = BeeperSettings {
+        frequency: SAMPLE_RATE as f32 / 4.0,
+        waveform: Waveform::Square,
+        volume: 0.5,
+        muted: false,
+    };
+    let mut beeper = Beeper::new(settings);
+    beeper.add_sink(Box::new(RecordingSink(samples.clone())));
+
+    assert_eq!(
+        beeper.generate(true, 8),
+        [0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5]
+    );
+    assert_eq!(beeper.generate(false, 2), [0.0, 0.0]);
+
+    beeper.toggle_mute();
+    assert_eq!(beeper.generate(true, 2), [0.0, 0.0]);
+    beeper.toggle_mute();
+
+    beeper.settings.waveform = Waveform::Sine;
+    let sine = beeper.generate(true, 4);
+    let expected = [0.0, 0.5, 0.0, -0.5];
+    for (sample, expected) in sine.iter().zip(expected.iter()) {
+        assert!((sample - expected).abs() < 0.001, "{:?}", sine);
+    }
+
+    // a quarter of a sample is carried over to the next tick, and nothing
+    // reaches the sinks before a flush
+    let tick = 100.25 / SAMPLE_RATE as f64;
+    beeper.advance(true, tick);
+    assert!(samples.borrow().is_empty());
+    beeper.flush();
+    assert_eq!(samples.borrow().len(), 100);
+    beeper.advance(true, tick);
+    beeper.advance(true, tick);
+    beeper.advance(true, tick);
+    beeper.flush();
+    assert_eq!(samples.borrow().len(), 401);
+}
+
+// A file in the temp directory, removed again when the test is done with it
+struct TempFile {
+    path: String,
+}
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("chip8_{}_{}", std::process::id(), name));
+        Self {
+            path: path.to_str().unwrap().to_string(),
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[test]
+fn test_repl_audio_out() {
+    let file = TempFile::new("audio.wav");
+    let path = file.path.as_str();
+
+    let mut vm = get_vm();
+    // LD V0, 0A; LD ST, V0; JP 204
+    vm.memory[0x200..0x206].copy_from_slice(&[0x60, 0x0A, 0xF0, 0x18, 0x12, 0x04]);
+    let mut repl = Repl::new(vm, Debugger::new(true), Tracer::new(false));
+    repl.beeper
+        .add_sink(Box::new(WavSink::create(path).unwrap()));
+
+    repl_command(&mut repl, "step 30");
+    repl.beeper.finish().unwrap();
+
+    let mut reader = hound::WavReader::open(path).unwrap();
+    assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+    assert_eq!(reader.spec().channels, 1);
+    let samples: Vec<i16> = reader.samples().map(|sample| sample.unwrap()).collect();
+
+    // 30 ticks of 110.25 samples, the sound timer runs from tick 2 to tick 10
+    assert_eq!(samples.len(), 3307);
+    let sounding: Vec<usize> = (0..samples.len())
+        .filter(|&index| samples[index] != 0)
+        .collect();
+    assert_eq!(sounding.len(), 992);
+    assert_eq!(sounding.first(), Some(&110));
+    assert_eq!(sounding.last(), Some(&1101));
+}
+
+#[test]
+fn test_palette() {
+    assert_eq!(Palette::default(), Theme::Classic.palette());
+    assert_eq!(Palette::default().background(), [0x00, 0x00, 0x00]);
+    assert_eq!(Palette::default().color(1), [0xFF, 0xFF, 0xFF]);
+
+    let amber = Theme::from_name("amber").unwrap();
+    assert_eq!(amber, Theme::Amber);
+    assert_eq!(Theme::from_name(&amber.to_string()), Ok(amber));
+    assert_eq!(Theme::from_name("high-contrast"), Ok(Theme::HighContrast));
+    assert!(Theme::from_name("sepia").is_err());
+
+    let mut palette = amber.palette();
+    palette.set_foreground("#112233").unwrap();
+    palette.set_background("445566").unwrap();
+    assert_eq!(
+        palette.colors,
+        [
+            [0x44, 0x55, 0x66],
+            [0x11, 0x22, 0x33],
+            [0x11, 0x22, 0x33],
+            [0x11, 0x22, 0x33]
+        ]
+    );
+    assert!(palette.set_foreground("12345").is_err());
+    assert!(palette.set_background("GGGGGG").is_err());
+
+    let palette = Palette::parse("000000,FF0000,00FF00,#0000FF").unwrap();
+    assert_eq!(palette.color(0), [0x00, 0x00, 0x00]);
+    assert_eq!(palette.color(2), [0x00, 0xFF, 0x00]);
+    assert_eq!(palette.color(3), [0x00, 0x00, 0xFF]);
+    assert!(Palette::parse("000000,FF0000").is_err());
+}
+
+#[test]
+fn test_framebuffer() {
+    let mut vm = get_vm();
+    let mut framebuffer = Framebuffer::default();
+    let palette = Theme::Amber.palette();
+    assert_eq!(framebuffer.rgba().len(), 64 * 32 * 4);
+
+    // the first frame is always uploaded
+    assert!(framebuffer.update(&vm.display_data, palette, AntiFlicker::Off));
+    assert!(!framebuffer.update(&vm.display_data, palette, AntiFlicker::Off));
+    let [red, green, blue] = palette.background();
+    assert_eq!(&framebuffer.rgba()[..4], &[red, green, blue, 0xFF]);
+
+    vm.display_data[65] = true;
+    assert!(framebuffer.update(&vm.display_data, palette, AntiFlicker::Off));
+    let [red, green, blue] = palette.color(1);
+    assert_eq!(
+        &framebuffer.rgba()[65 * 4..66 * 4],
+        &[red, green, blue, 0xFF]
+    );
+    assert!(!framebuffer.update(&vm.display_data, palette, AntiFlicker::Off));
+
+    // turning the pixel off again is a change too
+    vm.display_data[65] = false;
+    assert!(framebuffer.update(&vm.display_data, palette, AntiFlicker::Off));
+
+    let palette = Theme::GreenPhosphor.palette();
+    assert!(framebuffer.update(&vm.display_data, palette, AntiFlicker::Off));
+    let [red, green, blue] = palette.background();
+    assert_eq!(
+        &framebuffer.rgba()[65 * 4..66 * 4],
+        &[red, green, blue, 0xFF]
+    );
+}
+
+#[test]
+fn test_crt() {
+    assert_eq!(CrtPreset::from_name("lcd"), Ok(CrtPreset::Lcd));
+    assert!(CrtPreset::from_name("tv").is_err());
+    assert_eq!(CrtPreset::default().next(), CrtPreset::Scanlines);
+    assert_eq!(CrtPreset::Crt.next(), CrtPreset::Off);
+    assert_eq!(CrtPreset::Off.effects(), None);
+
+    assert_eq!(crt_scale(4.0), 4);
+    assert_eq!(crt_scale(6.6), 7);
+    assert_eq!(crt_scale(0.5), 1);
+
+    // a white 4x4 image with a black pixel in its middle
+    let (width, height, scale) = (4, 4, 4);
+    let mut rgba = vec![0xFF; width * height * 4];
+    rgba[(5 * 4)..(5 * 4 + 3)].copy_from_slice(&[0, 0, 0]);
+    let red =
+        |image: &[u8], image_width: usize, x: usize, y: usize| image[(y * image_width + x) * 4];
+
+    let scanlines = CrtPreset::Scanlines
+        .effects()
+        .unwrap()
+        .apply(&rgba, width, height, scale);
+    assert_eq!(scanlines.len(), width * height * scale * scale * 4);
+    assert_eq!(red(&scanlines, width * scale, 0, 0), 0xFF);
+    assert!(red(&scanlines, width * scale, 0, 1) < 0xFF);
+    // alpha is left opaque
+    assert_eq!(scanlines[3], 0xFF);
+
+    // the gap is the last row and column of every pixel at any scale
+    let lcd_effects = CrtPreset::Lcd.effects().unwrap();
+    for &scale in &[2, 3, 4] {
+        let lcd = lcd_effects.apply(&rgba, width, height, scale);
+        let image_width = width * scale;
+        assert_eq!(red(&lcd, image_width, 0, 0), 0xFF);
+        assert!(red(&lcd, image_width, scale - 1, 0) < 0xFF);
+        assert!(red(&lcd, image_width, 0, scale - 1) < 0xFF);
+        assert_eq!(red(&lcd, image_width, scale, 0), 0xFF);
+    }
+    // and there is no room for one at scale 1
+    let lcd = lcd_effects.apply(&rgba, width, height, 1);
+    assert_eq!(lcd.len(), rgba.len());
+    assert_eq!(red(&lcd, width, 0, 0), 0xFF);
+
+    // the corners are darker than the centre, and the black pixel glows
+    // from its lit neighbours
+    let image_width = width * scale;
+    let crt = CrtPreset::Crt
+        .effects()
+        .unwrap()
+        .apply(&rgba, width, height, scale);
+    assert!(red(&crt, image_width, 0, 0) < red(&crt, image_width, 8, 8));
+    assert!(red(&crt, image_width, scale, scale) > 0);
+    let mut effects = CrtPreset::Crt.effects().unwrap();
+    effects.bloom = 0.0;
+    let no_bloom = effects.apply(&rgba, width, height, scale);
+    assert_eq!(red(&no_bloom, image_width, scale, scale), 0);
+}
+
+#[test]
+fn test_crt_speed() {
+    // the glow blurs over a whole VM pixel, which must not add work for
+    // every image pixel as the scale grows. Compared to the same effects
+    // without glow so it holds on slow machines and in debug builds.
+    let (width, height) = (DISPLAY_SIZE[0], DISPLAY_SIZE[1]);
+    let rgba: Vec<u8> = (0..width * height * 4)
+        .map(|index| (index * 7919 % 251) as u8)
+        .collect();
+    let fastest = |effects: CrtEffects| {
+        (0..3)
+            .map(|_| {
+                let start = std::time::Instant::now();
+                effects.apply(&rgba, width, height, DEFAULT_SCALE);
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    };
+
+    let effects = CrtPreset::Crt.effects().unwrap();
+    let with_glow = fastest(effects);
+    let without_glow = fastest(CrtEffects {
+        bloom: 0.0,
+        ..effects
+    });
+    assert!(
+        with_glow < without_glow * 5,
+        "{:?} with glow, {:?} without",
+        with_glow,
+        without_glow
+    );
+}
+
+#[test]
+fn test_anti_flicker() {
+    let palette = Palette::parse("000000,FFFFFF,FFFFFF,FFFFFF").unwrap();
+    let red = |framebuffer: &Framebuffer, index: usize| framebuffer.rgba()[index * 4];
+    let mut display_data = [false; 64 * 32];
+
+    // a sprite erased and redrawn at a new position every other frame
+    let mut framebuffer = Framebuffer::default();
+    display_data[0] = true;
+    framebuffer.update(&display_data, palette, AntiFlicker::Blend);
+    assert_eq!(red(&framebuffer, 0), 0x80);
+    display_data[0] = false;
+    display_data[1] = true;
+    framebuffer.update(&display_data, palette, AntiFlicker::Blend);
+    assert_eq!((red(&framebuffer, 0), red(&framebuffer, 1)), (0x80, 0x80));
+    assert!(framebuffer.update(&display_data, palette, AntiFlicker::Blend));
+    assert_eq!((red(&framebuffer, 0), red(&framebuffer, 1)), (0x00, 0xFF));
+    assert!(!framebuffer.update(&display_data, palette, AntiFlicker::Blend));
+
+    let mut framebuffer = Framebuffer::default();
+    framebuffer.update(&display_data, palette, AntiFlicker::Or);
+    display_data[1] = false;
+    framebuffer.update(&display_data, palette, AntiFlicker::Or);
+    assert_eq!(red(&framebuffer, 1), 0xFF);
+    framebuffer.update(&display_data, palette, AntiFlicker::Or);
+    assert_eq!(red(&framebuffer, 1), 0x00);
+
+    let persistence = AntiFlicker::Persistence { decay: 0.5 };
+    let mut framebuffer = Framebuffer::default();
+    display_data[2] = true;
+    framebuffer.update(&display_data, palette, persistence);
+    assert_eq!(red(&framebuffer, 2), 0xFF);
+    display_data[2] = false;
+    let mut shown = vec![];
+    while framebuffer.update(&display_data, palette, persistence) {
+        shown.push(red(&framebuffer, 2));
+    }
+    assert_eq!(shown, vec![0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x00]);
+
+    assert_eq!(
+        AntiFlicker::from_name("persistence", 0.25),
+        Ok(AntiFlicker::Persistence { decay: 0.25 })
+    );
+    assert!(AntiFlicker::from_name("smooth", 0.5).is_err());
+}
+
+#[test]
+fn test_layout() {
+    // the default window keeps the debugger panels where they always were
+    assert_eq!(window_size(16, false), (1024.0, 512.0));
+    assert_eq!(window_size(16, true), (1624.0, 812.0));
+    let layout = Layout::new(window_size(16, true), true, Scaling::Integer);
+    assert_eq!(layout.display.position, [0.0, 0.0]);
+    assert_eq!(layout.display.size, [1024.0, 512.0]);
+    assert_eq!(layout.registers.position, [1024.0, 0.0]);
+    assert_eq!(layout.disassembly.position, [1324.0, 0.0]);
+    assert_eq!(layout.memory_table.position, [0.0, 512.0]);
+    assert_eq!(layout.memory_table.size, [724.0, 300.0]);
+    assert_eq!(layout.trace.position, [724.0, 512.0]);
+    assert_eq!(layout.call_stack.position, [1324.0, 512.0]);
+
+    // integer scaling letterboxes the display in the middle of the window
+    let layout = Layout::new((1000.0, 700.0), false, Scaling::Integer);
+    assert_eq!(layout.scale(), 15.0);
+    assert_eq!(layout.display.position, [20.0, 110.0]);
+    assert_eq!(layout.display.size, [960.0, 480.0]);
+    assert_eq!(layout.pixel_at([20.0, 110.0]), Some((0, 0)));
+    assert_eq!(layout.pixel_at([979.0, 589.0]), Some((63, 31)));
+    assert_eq!(layout.pixel_at([19.0, 300.0]), None);
+    assert_eq!(layout.pixel_at([500.0, 590.0]), None);
+
+    let layout = Layout::new((1000.0, 700.0), false, Scaling::Smooth);
+    assert_eq!(layout.scale(), 15.625);
+    assert_eq!(layout.display.position, [0.0, 100.0]);
+
+    let layout = Layout::new((1600.0, 1100.0), true, Scaling::Integer);
+    assert_eq!(layout.display.size, [960.0, 480.0]);
+    assert_eq!(layout.registers.size, [300.0, 800.0]);
+    assert_eq!(layout.call_stack.position, [1300.0, 800.0]);
+
+    assert_eq!(min_window_size(false), (256.0, 128.0));
+    assert_eq!(min_window_size(true), (1200.0, 428.0));
+}
+
+fn read_png(path: &str) -> (u32, u32, Vec<u8>) {
+    let decoder = png::Decoder::new(std::fs::File::open(path).unwrap());
+    let (info, mut reader) = decoder.read_info().unwrap();
+    assert_eq!(info.color_type, png::ColorType::RGB);
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    (info.width, info.height, pixels)
+}
+
+#[test]
+fn test_screenshot() {
+    let file = TempFile::new("screenshot.png");
+    let path = file.path.as_str();
+    let palette = Theme::Amber.palette();
+
+    let mut display_data = [false; 64 * 32];
+    display_data[64 + 3] = true;
+    save_screenshot(path, &display_data, palette, 1).unwrap();
+    let (width, height, pixels) = read_png(path);
+    assert_eq!((width, height), (64, 32));
+    let expected: Vec<u8> = display_data
+        .iter()
+        .flat_map(|&lit| palette.color(lit as usize).to_vec())
+        .collect();
+    assert_eq!(pixels, expected);
+
+    assert!(save_screenshot("/nonexistent/screenshot.png", &display_data, palette, 1).is_err());
+}
+
+#[test]
+fn test_repl_screenshot() {
+    let file = TempFile::new("repl.png");
+    let path = file.path.as_str();
+
+    // LD V0, 01; LD V1, 02; LD F, V2; DRW V0, V1, 5
+    let vm = Chip8VM::new(&[0x60, 0x01, 0x61, 0x02, 0xF2, 0x29, 0xD0, 0x15]);
+    let mut repl = Repl::new(vm, Debugger::new(true), Tracer::new(false));
+    repl.palette = Theme::GreenPhosphor.palette();
+    repl_command(&mut repl, "step 4");
+
+    assert_eq!(
+        repl_command(&mut repl, &format!("screenshot {} 2", path)),
+        format!("Screenshot written to {}", path)
+    );
+    let (width, height, pixels) = read_png(path);
+    assert_eq!((width, height), (128, 64));
+    let color_at = |x: usize, y: usize| &pixels[(y * 128 + x) * 3..(y * 128 + x) * 3 + 3];
+    let (background, foreground) = (repl.palette.color(0), repl.palette.color(1));
+    assert_eq!(color_at(0, 0), background);
+    // the top row of the 0 glyph covers x 1-4 at y 2, two image pixels each
+    assert_eq!(color_at(2, 4), foreground);
+    assert_eq!(color_at(9, 5), foreground);
+    assert_eq!(color_at(10, 4), background);
+    assert_eq!(color_at(4, 6), background);
+
+    assert_eq!(
+        repl_command(&mut repl, &format!("screenshot {} 0", path)),
+        "Error: Invalid scale '0', expected 1 to 64"
+    );
+    // too large to allocate
+    assert_eq!(
+        repl_command(&mut repl, &format!("screenshot {} 100000", path)),
+        "Error: Invalid scale '100000', expected 1 to 64"
+    );
+}
+
+#[test]
+fn test_repl_recording() {
+    let (gif_file, raw_file) = (TempFile::new("recording.gif"), TempFile::new("frames.rgb"));
+    let (gif_path, raw_path) = (gif_file.path.clone(), raw_file.path.clone());
+    let palette = Theme::Amber.palette();
+
+    // LD V0, 00; LD F, V0; DRW V0, V0, 5; JP 206
+    let vm = Chip8VM::new(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06]);
+    let mut repl = Repl::new(vm, Debugger::new(true), Tracer::new(false));
+    repl.recorder
+        .add_sink(Box::new(GifSink::create(&gif_path, palette).unwrap()));
+    repl.recorder
+        .add_sink(Box::new(RawSink::create(&raw_path, palette).unwrap()));
+    assert!(repl.recorder.is_recording());
+
+    // a frame every 8 instructions, the first one before the sprite is drawn
+    repl_command(&mut repl, "step 40");
+    assert_eq!(
+        repl.recorder.finish(),
+        Ok(vec![gif_path.clone(), raw_path.clone()])
+    );
+    assert!(!repl.recorder.is_recording());
+
+    let raw = std::fs::read(&raw_path).unwrap();
+    assert_eq!(raw.len(), 5 * 64 * 32 * 3);
+    assert_eq!(&raw[..3], &palette.color(0));
+    assert_eq!(&raw[64 * 32 * 3..64 * 32 * 3 + 3], &palette.color(1));
+
+    let mut decoder = gif::Decoder::new(std::fs::File::open(&gif_path).unwrap());
+    decoder.set(gif::ColorOutput::Indexed);
+    let mut reader = decoder.read_info().unwrap();
+    assert_eq!((reader.width(), reader.height()), (64, 32));
+    assert_eq!(
+        &reader.global_palette().unwrap()[..6],
+        &[palette.color(0), palette.color(1)].concat()[..]
+    );
+    let mut frames = vec![];
+    while let Some(frame) = reader.read_next_frame().unwrap() {
+        frames.push((frame.delay, frame.buffer[0], frame.buffer[4]));
+    }
+    // the unchanged frames after the draw are merged into one
+    assert_eq!(frames, vec![(2, 0, 0), (8, 1, 0)]);
+}
+
+fn press(tui: &mut Tui, code: crossterm::event::KeyCode) -> bool {
+    tui.handle_key(crossterm::event::KeyEvent::new(
+        code,
+        crossterm::event::KeyModifiers::NONE,
+    ))
+}
+
+#[test]
+fn test_run_cycle() {
+    // LD V0, K; JP 202
+    let mut vm = get_vm();
+    vm.memory[0x200..0x204].copy_from_slice(&[0xF0, 0x0A, 0x12, 0x02]);
+    let mut debugger = Debugger::new(false);
+    let mut beeper = Beeper::new(BeeperSettings::default());
+    let mut recorder = Recorder::default();
+    let mut recorded = Vec::new();
+    let mut cycle = |vm: &mut Chip8VM, debugger: &mut Debugger| {
+        run_cycle(
+            vm,
+            debugger,
+            &mut beeper,
+            &mut recorder,
+            |program_counter, opcode, _, _, _| recorded.push((program_counter, opcode)),
+        )
+    };
+
+    assert_eq!(cycle(&mut vm, &mut debugger), Ok(true));
+    assert!(vm.waiting_for_key_press);
+    assert_eq!(cycle(&mut vm, &mut debugger), Ok(false));
+
+    // taking the key is a cycle of its own
+    vm.pressed_key = Some(0x5);
+    assert_eq!(cycle(&mut vm, &mut debugger), Ok(false));
+    assert_eq!(vm.v[0], 0x5);
+    assert!(!vm.waiting_for_key_press);
+    assert_eq!(cycle(&mut vm, &mut debugger), Ok(true));
+
+    // a paused VM doesn't run
+    debugger.pause();
+    assert_eq!(cycle(&mut vm, &mut debugger), Ok(false));
+    assert_eq!(vm.program_counter, 0x202);
+    assert_eq!(recorded, [(0x200, 0xF00A), (0x202, 0x1202)]);
+}
+
+#[test]
+fn test_tui_screen() {
+    let mut vm = get_vm();
+    vm.display_data[0] = true;
+    vm.display_data[64 + 1] = true;
+    vm.display_data[2] = true;
+    vm.display_data[64 + 2] = true;
+    vm.v[0xA] = 0x3C;
+    vm.i = 0x2A4;
+
+    let lines = screen_lines(&vm, "Running");
+    // a frame around 16 lines of half blocks, then the status and help lines
+    assert_eq!(lines.len(), 20);
+    assert_eq!(lines[0], format!("┌{}┐", "─".repeat(64)));
+    assert!(lines[1].starts_with("│▀▄█ "));
+    assert!(lines[1].ends_with("│ PC 200   I 2A4"));
+    assert_eq!(lines[2].chars().count(), 66 + 1 + "DT 00    ST 00".len());
+    assert!(lines[7].ends_with("│ V2 00    VA 3C"));
+    assert_eq!(lines[16], format!("│{}│", " ".repeat(64)));
+    assert_eq!(lines[17], format!("└{}┘", "─".repeat(64)));
+    assert_eq!(lines[18], "Running");
+}
+
+#[test]
+fn test_tui_keys() {
+    use crossterm::event::KeyCode;
+
+    // LD V0, K; JP 202
+    let mut vm = get_vm();
+    vm.memory[0x200..0x204].copy_from_slice(&[0xF0, 0x0A, 0x12, 0x02]);
+    let mut tui = Tui::new(vm, Debugger::new(false));
+    tui.run_cycles(10).unwrap();
+    assert!(tui.chip8_state.waiting_for_key_press);
+
+    // terminals only report presses, the key is held for a while
+    assert!(!press(&mut tui, KeyCode::Char('W')));
+    assert_eq!(tui.chip8_state.pressed_key, Some(0x5));
+    tui.run_cycles(KEY_HOLD_CYCLES as u64 - 1).unwrap();
+    assert_eq!(tui.chip8_state.v[0], 0x5);
+    assert!(!tui.chip8_state.waiting_for_key_press);
+    assert_eq!(tui.chip8_state.pressed_key, Some(0x5));
+    tui.run_cycles(1).unwrap();
+    assert_eq!(tui.chip8_state.pressed_key, None);
+
+    assert!(!press(&mut tui, KeyCode::Char('p')));
+    assert!(!tui.debugger.is_running());
+    let program_counter = tui.chip8_state.program_counter;
+    tui.run_cycles(10).unwrap();
+    assert_eq!(tui.chip8_state.program_counter, program_counter);
+    assert!(!press(&mut tui, KeyCode::Char('p')));
+    assert!(tui.debugger.is_running());
+
+    assert!(!press(&mut tui, KeyCode::Char('m')));
+    assert!(tui.beeper.settings.muted);
+    assert!(!press(&mut tui, KeyCode::Char('c')));
+    assert_eq!(tui.chip8_state.pressed_key, Some(0xB));
+    assert!(press(&mut tui, KeyCode::Esc));
+
+    let mut debugger = Debugger::new(false);
+    debugger.on_unknown_opcode = UnknownOpcodeAction::Fatal;
+    let mut vm = get_vm();
+    vm.memory[0x200..0x202].copy_from_slice(&[0x01, 0x23]);
+    let mut tui = Tui::new(vm, debugger);
+    assert_eq!(
+        tui.run_cycles(1),
+        Err("Unknown opcode 0123 at 200".to_string())
+    );
+
+    // the terminal can't take printed text, the opcode stays for the status line
+    let mut vm = get_vm();
+    vm.memory[0x200..0x202].copy_from_slice(&[0x01, 0x23]);
+    let mut tui = Tui::new(vm, Debugger::new(false));
+    tui.run_cycles(1).unwrap();
+    assert_eq!(
+        tui.skipped_unknown_opcode,
+        Some(UnknownOpcode {
+            address: 0x200,
+            opcode: 0x0123,
+        })
+    );
+    tui.run_cycles(1).unwrap();
+    assert!(tui.skipped_unknown_opcode.is_some());
+}
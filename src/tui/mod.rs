@@ -0,0 +1,207 @@
+// Plays a ROM in the terminal for machines where no window can be opened,
+// e.g. over SSH or in a container. Terminals only report key presses, so a
+// pressed key is held down for a moment instead of until it's released.
+
+mod screen;
+
+pub use screen::screen_lines;
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::Print,
+    terminal::{self, ClearType},
+};
+use ggez::event::KeyCode as WindowKeyCode;
+
+use crate::audio::{Beeper, BeeperSettings};
+use crate::chip8_vm::{Chip8VM, UnknownOpcode, CYCLES_PER_SECOND};
+use crate::debug::{run_cycle, Debugger};
+use crate::display::Recorder;
+
+// A quarter of a second, longer than the delay before a terminal starts
+// repeating a held key would leave gaps in the press
+pub const KEY_HOLD_CYCLES: u32 = CYCLES_PER_SECOND / 4;
+const FRAME_TIME: Duration = Duration::from_millis(1000 / 60);
+// after a stall (a suspended terminal) the VM doesn't try to catch up more
+// than this
+const MAX_CYCLES_PER_FRAME: u64 = CYCLES_PER_SECOND as u64 / 10;
+
+// The keys of the emulator window's keypad layout
+fn window_key(character: char) -> Option<WindowKeyCode> {
+    Some(match character.to_ascii_lowercase() {
+        '1' => WindowKeyCode::Key1,
+        '2' => WindowKeyCode::Key2,
+        '3' => WindowKeyCode::Key3,
+        '4' => WindowKeyCode::Key4,
+        'q' => WindowKeyCode::Q,
+        'w' => WindowKeyCode::W,
+        'e' => WindowKeyCode::E,
+        'r' => WindowKeyCode::R,
+        'a' => WindowKeyCode::A,
+        's' => WindowKeyCode::S,
+        'd' => WindowKeyCode::D,
+        'f' => WindowKeyCode::F,
+        'z' => WindowKeyCode::Z,
+        'x' => WindowKeyCode::X,
+        'c' => WindowKeyCode::C,
+        'v' => WindowKeyCode::V,
+        _ => return None,
+    })
+}
+
+pub struct Tui {
+    pub chip8_state: Chip8VM,
+    pub debugger: Debugger,
+    pub beeper: Beeper,
+    pub recorder: Recorder,
+    // the last unknown opcode the VM skipped, shown in the status line as
+    // printing it would garble the screen
+    pub skipped_unknown_opcode: Option<UnknownOpcode>,
+    // cycles until the pressed key is released
+    key_hold: u32,
+}
+
+impl Tui {
+    pub fn new(chip8_state: Chip8VM, debugger: Debugger) -> Self {
+        Self {
+            chip8_state,
+            debugger,
+            beeper: Beeper::new(BeeperSettings::default()),
+            recorder: Recorder::default(),
+            skipped_unknown_opcode: None,
+            key_hold: 0,
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        queue!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        let result = self.play(&mut stdout);
+
+        // give the terminal back even when the ROM failed
+        queue!(stdout, cursor::Show, terminal::LeaveAlternateScreen)?;
+        stdout.flush()?;
+        terminal::disable_raw_mode()?;
+
+        self.beeper.finish()?;
+        for path in self.recorder.finish().map_err(io::Error::other)? {
+            println!("Recording written to {}", path);
+        }
+        result
+    }
+
+    fn play(&mut self, stdout: &mut io::Stdout) -> io::Result<()> {
+        let start = Instant::now();
+        let mut cycles_run = 0;
+        queue!(stdout, terminal::Clear(ClearType::All))?;
+
+        loop {
+            while event::poll(Duration::from_secs(0))? {
+                match event::read()? {
+                    Event::Key(key) if self.handle_key(key) => return Ok(()),
+                    Event::Resize(_, _) => queue!(stdout, terminal::Clear(ClearType::All))?,
+                    _ => (),
+                }
+            }
+
+            let due = (start.elapsed().as_secs_f64() * CYCLES_PER_SECOND as f64) as u64;
+            let cycles = (due - cycles_run).min(MAX_CYCLES_PER_FRAME);
+            cycles_run = due;
+            self.run_cycles(cycles).map_err(io::Error::other)?;
+
+            for (row, line) in screen_lines(&self.chip8_state, &self.status())
+                .iter()
+                .enumerate()
+            {
+                queue!(
+                    stdout,
+                    cursor::MoveTo(0, row as u16),
+                    Print(line),
+                    terminal::Clear(ClearType::UntilNewLine)
+                )?;
+            }
+            stdout.flush()?;
+
+            std::thread::sleep(FRAME_TIME);
+        }
+    }
+
+    // Returns true when the user quits
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if key.kind == KeyEventKind::Release {
+            return false;
+        }
+        match key.code {
+            KeyCode::Esc => return true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+            KeyCode::Char('p') | KeyCode::Char('P') if self.debugger.is_running() => {
+                self.debugger.pause()
+            }
+            KeyCode::Char('p') | KeyCode::Char('P') => self.debugger.resume(),
+            KeyCode::Char('m') | KeyCode::Char('M') => self.beeper.toggle_mute(),
+            KeyCode::Char(character) => {
+                if let Some(keycode) = window_key(character) {
+                    self.chip8_state.handle_keyboard_input(keycode);
+                    self.key_hold = KEY_HOLD_CYCLES;
+                }
+            }
+            _ => (),
+        }
+        false
+    }
+
+    // Runs like the emulator window does, fails on a fatal unknown opcode
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<(), String> {
+        for _ in 0..cycles {
+            let stepped = run_cycle(
+                &mut self.chip8_state,
+                &mut self.debugger,
+                &mut self.beeper,
+                &mut self.recorder,
+                |_, _, _, _, _| (),
+            )?;
+            if stepped {
+                if let Some(unknown) = self.debugger.unknown_opcode_skipped(&self.chip8_state) {
+                    self.skipped_unknown_opcode = Some(unknown);
+                }
+            }
+
+            if self.key_hold > 0 {
+                self.key_hold -= 1;
+                if self.key_hold == 0 {
+                    self.chip8_state.pressed_key = None;
+                }
+            }
+        }
+        self.beeper.flush();
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        let mut status = match self.debugger.unknown_opcode_hit {
+            Some(unknown) if !self.debugger.is_running() => format!(
+                "Unknown opcode {:04X?} at {:03X?}, paused",
+                unknown.opcode, unknown.address
+            ),
+            _ if !self.debugger.is_running() => "Paused".to_string(),
+            _ => "Running".to_string(),
+        };
+        if let Some(unknown) = self.skipped_unknown_opcode {
+            status.push_str(&format!(
+                ", skipped unknown opcode {:04X?} at {:03X?}",
+                unknown.opcode, unknown.address
+            ));
+        }
+        if self.beeper.settings.muted {
+            status.push_str(", muted");
+        }
+        status
+    }
+}
@@ -0,0 +1,69 @@
+use crate::chip8_vm::{Chip8VM, DISPLAY_SIZE};
+
+const HELP: &str = "keypad: 1234 QWER ASDF ZXCV   P: pause   M: mute   Esc: quit";
+
+// Two display rows per terminal line, the upper pixel is the top half of
+// the character cell
+fn half_block(top: bool, bottom: bool) -> char {
+    match (top, bottom) {
+        (false, false) => ' ',
+        (true, false) => '▀',
+        (false, true) => '▄',
+        (true, true) => '█',
+    }
+}
+
+fn display_lines(display_data: &[bool]) -> Vec<String> {
+    display_data
+        .chunks_exact(DISPLAY_SIZE[0] * 2)
+        .map(|rows| {
+            let (top, bottom) = rows.split_at(DISPLAY_SIZE[0]);
+            top.iter()
+                .zip(bottom)
+                .map(|(&top, &bottom)| half_block(top, bottom))
+                .collect()
+        })
+        .collect()
+}
+
+fn register_lines(chip8_state: &Chip8VM) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "PC {:03X?}   I {:03X?}",
+            chip8_state.program_counter, chip8_state.i
+        ),
+        format!(
+            "DT {:02X?}    ST {:02X?}",
+            chip8_state.delay_timer, chip8_state.sound_timer
+        ),
+        format!("SP {:X?}", chip8_state.stack_pointer),
+        String::new(),
+    ];
+    for row in 0..8 {
+        lines.push(format!(
+            "V{:X?} {:02X?}    V{:X?} {:02X?}",
+            row,
+            chip8_state.v[row],
+            row + 8,
+            chip8_state.v[row + 8]
+        ));
+    }
+    lines
+}
+
+// The display in a frame with the registers beside it, then a status line
+// and the key help
+pub fn screen_lines(chip8_state: &Chip8VM, status: &str) -> Vec<String> {
+    let border = "─".repeat(DISPLAY_SIZE[0]);
+    let registers = register_lines(chip8_state);
+
+    let mut lines = vec![format!("┌{}┐", border)];
+    for (row, line) in display_lines(&chip8_state.display_data).iter().enumerate() {
+        let registers = registers.get(row).map(String::as_str).unwrap_or("");
+        lines.push(format!("│{}│ {}", line, registers).trim_end().to_string());
+    }
+    lines.push(format!("└{}┘", border));
+    lines.push(status.to_string());
+    lines.push(HELP.to_string());
+    lines
+}
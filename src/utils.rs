@@ -1,10 +1,13 @@
 use std::{
     fs::File,
     io::{Read, Write},
+    ops::RangeInclusive,
+    path::Path,
 };
 
 pub fn read_file_as_bytes(filename: &str) -> Result<Vec<u8>, String> {
-    let mut f = File::open(&filename).expect("no file found");
+    let mut f = File::open(filename)
+        .map_err(|error| format!("Error with opening '{}': {}", filename, error))?;
     let mut buffer = Vec::<u8>::new();
     match f.read_to_end(&mut buffer) {
         Ok(_) => Ok(buffer),
@@ -12,6 +15,38 @@ pub fn read_file_as_bytes(filename: &str) -> Result<Vec<u8>, String> {
     }
 }
 
+// NAME-1.EXT, NAME-2.EXT, ... in the working directory, never overwriting an
+// earlier file
+pub fn next_free_path(name: &str, extension: &str) -> String {
+    (1..)
+        .map(|number| format!("{}-{}.{}", name, number, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
 pub fn byte_copy(from: &[u8], mut to: &mut [u8]) -> usize {
     to.write(from).unwrap()
 }
+
+// Accepts both "2A4" and "0x2A4" forms
+pub fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text
+        .trim()
+        .trim_start_matches("0x")
+        .trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address '{}'", text))
+}
+
+pub fn parse_address_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let mut parts = text.splitn(2, '-');
+    let start = parse_address(parts.next().unwrap_or(""))?;
+    let end = match parts.next() {
+        Some(end) => parse_address(end)?,
+        None => return Err(format!("Invalid address range '{}'", text)),
+    };
+
+    if start > end {
+        return Err(format!("Invalid address range '{}'", text));
+    }
+    Ok(start..=end)
+}